@@ -1,10 +1,27 @@
+use crate::memory::Memory;
+use crate::ppu::OAM_SIZE;
+use block_cache::{BlockCache, CodeBank, DecodedInstruction};
+use breakpoints::{BreakHit, Breakpoint, Breakpoints};
 use bus::Bus;
+use cdl::{CDL_CODE, CDL_DATA, CDL_INDIRECT_CODE, CDL_INDIRECT_DATA};
+use disassembler::{disassemble, Disassembly};
+use opcodes::{AddressingMode, Opcode, Operation, CMOS_OPCODES, OPCODES};
+use profiler::Profiler;
 use std::fmt;
-use std::thread::sleep;
-use std::time::Duration;
+use trace::Tracer;
 
+mod block_cache;
+pub mod breakpoints;
 pub mod bus;
+pub mod cdl;
+mod cmos;
 pub mod controller;
+pub mod disassembler;
+pub mod gdb;
+pub mod monitor;
+pub mod opcodes;
+pub mod profiler;
+pub mod trace;
 
 const STATUS_NEGATIVE_BIT: u32 = 7;
 const STATUS_OVERFLOW_BIT: u32 = 6;
@@ -24,13 +41,92 @@ const INTERRUPT_VECTOR_RES_HI: u16 = 0xFFFD;
 const INTERRUPT_VECTOR_IRQ_LO: u16 = 0xFFFE;
 const INTERRUPT_VECTOR_IRQ_HI: u16 = 0xFFFF;
 
-struct Registers {
-    pc: u16, // Program Counter
-    a: u8,   // Accumulator
-    x: u8,   // Index Register X
-    y: u8,   // Index Register Y
-    p: u8,   // Status Register
-    s: u8,   // Stack Pointer
+/// Everything the CPU is connected to: memory accesses and the interrupt lines
+pub trait CpuBus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Reads without side effects, used by the debugging tools
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Called once for every CPU cycle, before the memory access of that cycle
+    fn tick(&mut self) {}
+
+    /// Brings the rest of the system into its power-on state
+    fn power_on(&mut self) {}
+
+    /// Forwards the reset signal to the rest of the system
+    fn reset(&mut self) {}
+
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    fn poll_irq(&self) -> bool {
+        false
+    }
+
+    /// Whether an NMI is waiting to be serviced, without acknowledging it
+    fn nmi_pending(&self) -> bool {
+        false
+    }
+
+    /// Returns the page that should be copied to OAM after a write to $4014
+    fn poll_oam_dma(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Tags a byte that was read for the code/data logger, `flags` are the `CDL_*` PRG flags
+    fn log_code_data(&mut self, _addr: u16, _flags: u8) {}
+
+    /// Identifies the memory mapped at `addr` for the block cache, `None` if it can't be cached
+    fn code_bank(&self, _addr: u16) -> Option<CodeBank> {
+        None
+    }
+
+    /// Whether a write to `addr` can change what `code_bank` identifies
+    fn switches_banks(&self, _addr: u16) -> bool {
+        true
+    }
+
+    /// The scanline and dot of the PPU for trace logs, if there is one
+    fn ppu_position(&self) -> Option<(u32, u32)> {
+        None
+    }
+}
+
+/// Plain memory is a bus without any interrupt sources
+impl<M: Memory> CpuBus for M {
+    fn read(&mut self, addr: u16) -> u8 {
+        Memory::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        Memory::write(self, addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Memory::read(self, addr)
+    }
+
+    fn code_bank(&self, _addr: u16) -> Option<CodeBank> {
+        Some(CodeBank::Ram)
+    }
+
+    fn switches_banks(&self, _addr: u16) -> bool {
+        false
+    }
+}
+
+/// The programmer visible registers, a snapshot can be taken by cloning them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16, // Program Counter
+    pub a: u8,   // Accumulator
+    pub x: u8,   // Index Register X
+    pub y: u8,   // Index Register Y
+    pub p: u8,   // Status Register
+    pub s: u8,   // Stack Pointer
 }
 
 impl fmt::Display for Registers {
@@ -91,45 +187,45 @@ impl Registers {
         self.p & (1 << bit) != 0
     }
 
-    fn update_carry_bit(&mut self, value: bool) {
+    pub fn update_carry_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_CARRY_BIT, value)
     }
-    fn get_carry_bit(&self) -> bool {
+    pub fn get_carry_bit(&self) -> bool {
         self.get_status_bit(STATUS_CARRY_BIT)
     }
 
-    fn update_zero_bit(&mut self, value: bool) {
+    pub fn update_zero_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_ZERO_BIT, value)
     }
-    fn get_zero_bit(&self) -> bool {
+    pub fn get_zero_bit(&self) -> bool {
         self.get_status_bit(STATUS_ZERO_BIT)
     }
 
-    fn update_interupt_bit(&mut self, value: bool) {
+    pub fn update_interrupt_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_INTERRUPT_BIT, value)
     }
-    fn get_interupt_bit(&self) -> bool {
+    pub fn get_interrupt_bit(&self) -> bool {
         self.get_status_bit(STATUS_INTERRUPT_BIT)
     }
 
-    fn update_decimal_bit(&mut self, value: bool) {
+    pub fn update_decimal_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_DECIMAL_BIT, value)
     }
-    fn get_decimal_bit(&self) -> bool {
+    pub fn get_decimal_bit(&self) -> bool {
         self.get_status_bit(STATUS_DECIMAL_BIT)
     }
 
-    fn update_overflow_bit(&mut self, value: bool) {
+    pub fn update_overflow_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_OVERFLOW_BIT, value)
     }
-    fn get_overflow_bit(&self) -> bool {
+    pub fn get_overflow_bit(&self) -> bool {
         self.get_status_bit(STATUS_OVERFLOW_BIT)
     }
 
-    fn update_negative_bit(&mut self, value: bool) {
+    pub fn update_negative_bit(&mut self, value: bool) {
         self.update_status_bit(STATUS_NEGATIVE_BIT, value)
     }
-    fn get_negative_bit(&self) -> bool {
+    pub fn get_negative_bit(&self) -> bool {
         self.get_status_bit(STATUS_NEGATIVE_BIT)
     }
 }
@@ -142,48 +238,191 @@ enum Access {
 
 const NES_CPU_OPTIONS: CpuOptions = CpuOptions {
     ignore_decimal_bit: true,
+    variant: CpuVariant::Nmos6502,
 };
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// The original NMOS 6502, including the unofficial opcodes
+    #[default]
+    Nmos6502,
+    /// The WDC 65C02 with its additional instructions and addressing modes
+    Wdc65C02,
+}
+
+impl CpuVariant {
+    pub fn opcodes(self) -> &'static [Opcode; 256] {
+        match self {
+            CpuVariant::Nmos6502 => &OPCODES,
+            CpuVariant::Wdc65C02 => &CMOS_OPCODES,
+        }
+    }
+}
+
+/// Errors that halt the CPU until it is reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// One of the KIL/JAM opcodes locked up the CPU, like on real hardware only a reset recovers
+    Jammed { opcode: u8, addr: u16 },
+    /// An unstable unofficial opcode that is not emulated
+    UnsupportedOpcode { opcode: u8, addr: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::Jammed { opcode, addr } => {
+                write!(f, "CPU jammed by opcode ${:02X} at ${:04X}", opcode, addr)
+            }
+            CpuError::UnsupportedOpcode { opcode, addr } => {
+                write!(f, "Unsupported opcode ${:02X} at ${:04X}", opcode, addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+/// Interrupts that are signalled to the CPU
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PendingInterrupts {
+    pub nmi: bool,
+    /// The IRQ line is asserted, it is only serviced while the interrupt flag is clear
+    pub irq: bool,
+}
+
 #[derive(Default)]
 pub struct CpuOptions {
-    ignore_decimal_bit: bool,
+    pub ignore_decimal_bit: bool,
+    pub variant: CpuVariant,
 }
 
-pub struct Cpu {
+pub struct Cpu<B: CpuBus> {
     options: CpuOptions,
     registers: Registers,
-    pub bus: Bus,
-    clock_speed: u32,
-    cycle: u32,
+    pub bus: B,
+    cycle: u64,
+    irq_inhibited: bool,
+    waiting_for_interrupt: bool,
+    stopped: bool,
+    halted: Option<CpuError>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    block_cache: Option<BlockCache>,
+    /// The current instruction, if it was taken from the block cache
+    decoded: Option<DecodedInstruction>,
+    breakpoints: Breakpoints,
+    break_hit: Option<BreakHit>,
+    resumed_at: Option<u16>,
+    instruction_pc: u16,
+    /// Code/data logger flags for the reads of the current instruction
+    code_flags: u8,
+    data_flags: u8,
+    jumped_indirectly: bool,
 }
 
-impl Cpu {
+impl<B: CpuBus> Cpu<B> {
+    fn is_cmos(&self) -> bool {
+        self.options.variant == CpuVariant::Wdc65C02
+    }
+
     fn clock_cycle(&mut self) {
-        if self.clock_speed == 0 {
-            return;
-        }
         self.cycle += 1;
-        let sec: f64 = 1.0 / f64::from(self.clock_speed);
-        sleep(Duration::from_secs_f64(sec));
+        self.bus.tick();
     }
 
-    fn read_memory(&mut self, a: u16) -> u8 {
+    fn read_bus(&mut self, a: u16) -> u8 {
         self.clock_cycle();
-        self.bus.read(a)
+        let value = self.bus.read(a);
+        self.check_read_breakpoints(a, value);
+        value
+    }
+
+    fn check_read_breakpoints(&mut self, a: u16, value: u8) {
+        if let Some(breakpoint) = self.breakpoints.check_read(a) {
+            self.break_on(breakpoint, a, Some(value));
+        }
+    }
+
+    fn read_memory(&mut self, a: u16) -> u8 {
+        let value = self.read_bus(a);
+        self.bus.log_code_data(a, self.data_flags);
+        value
+    }
+
+    /// Reads a byte of the current instruction
+    fn fetch(&mut self, a: u16) -> u8 {
+        let value = match self.decoded_byte(a) {
+            // reading cacheable memory again has no effect, only the cycle passes
+            Some(value) => {
+                self.clock_cycle();
+                self.check_read_breakpoints(a, value);
+                value
+            }
+            None => self.read_bus(a),
+        };
+        self.bus.log_code_data(a, self.code_flags);
+        value
+    }
+
+    fn decoded_byte(&self, a: u16) -> Option<u8> {
+        let decoded = self.decoded.as_ref()?;
+        let offset = a.wrapping_sub(decoded.addr);
+        (offset < decoded.len).then(|| decoded.bytes[offset as usize])
+    }
+
+    /// A read whose value is discarded, it is not logged as data
+    fn dummy_read(&mut self, a: u16) {
+        self.read_bus(a);
     }
 
     fn write_memory(&mut self, a: u16, v: u8) {
         self.clock_cycle();
-        self.bus.write(a, v)
+        self.bus.write(a, v);
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.write(&self.bus, a);
+            // JSR can push the return address over its own operand before fetching it
+            if self.decoded_byte(a).is_some() {
+                self.decoded = None;
+            }
+        }
+        if let Some(breakpoint) = self.breakpoints.check_write(a) {
+            self.break_on(breakpoint, a, Some(v));
+        }
+        if let Some(page) = self.bus.poll_oam_dma() {
+            self.oam_dma(page);
+        }
+    }
+
+    /// The CPU halts while the DMA unit copies a page of memory to OAM, one read and one write per byte
+    fn oam_dma(&mut self, page: u8) {
+        self.clock_cycle();
+        // the transfer has to start on an even cycle
+        if self.cycle % 2 == 1 {
+            self.clock_cycle();
+        }
+        for i in 0..OAM_SIZE {
+            let value = self.read_memory(((page as u16) << 8) | (i as u16));
+            self.write_memory(0x2004, value);
+        }
     }
 
     fn next(&mut self) -> u8 {
-        let current = self.read_memory(self.registers.pc);
+        let current = self.fetch(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
         current
     }
 
+    /// Instructions without an operand still read the byte after the opcode
+    fn dummy_read_next(&mut self) {
+        self.dummy_read(self.registers.pc);
+    }
+
+    fn dummy_read_stack(&mut self) {
+        self.dummy_read(0x0100 + self.registers.s as u16);
+    }
+
     fn addr_zeropage(&mut self) -> u16 {
         let addr = self.next();
         u16::from(addr)
@@ -191,15 +430,15 @@ impl Cpu {
 
     fn addr_zeropage_x(&mut self) -> u16 {
         let addr = self.next();
+        self.dummy_read(u16::from(addr));
         let addr = addr.wrapping_add(self.registers.x);
-        self.clock_cycle();
         u16::from(addr)
     }
 
     fn addr_zeropage_y(&mut self) -> u16 {
         let addr = self.next();
+        self.dummy_read(u16::from(addr));
         let addr = addr.wrapping_add(self.registers.y);
-        self.clock_cycle();
         u16::from(addr)
     }
 
@@ -209,59 +448,49 @@ impl Cpu {
         u16::from(lo) | (u16::from(hi) << 8)
     }
 
-    fn addr_absolute_x(&mut self, access: Access) -> u16 {
-        let lo = self.next();
-        let hi = self.next();
-        let base = u16::from(lo) | (u16::from(hi) << 8);
-
-        let x = self.registers.x as u16;
-        let addr = base.wrapping_add(x);
+    fn addr_indexed(&mut self, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
 
+        // the low byte is added first, so the CPU reads from the wrong page before fixing the high byte
         let page_cross = (base & 0xFF00) != (addr & 0xFF00);
+        let unfixed_addr = if self.is_cmos() {
+            // the 65C02 reads the last instruction byte again instead
+            self.registers.pc.wrapping_sub(1)
+        } else {
+            (base & 0xFF00) | (addr & 0x00FF)
+        };
         match access {
             Access::Read => {
                 if page_cross {
-                    self.clock_cycle();
+                    self.dummy_read(unfixed_addr);
                 }
             }
             Access::Write | Access::ReadModify => {
-                self.clock_cycle();
+                self.dummy_read(unfixed_addr);
             }
         }
 
         addr
     }
 
-    fn addr_absolute_y(&mut self, access: Access) -> u16 {
-        let lo = self.next();
-        let hi = self.next();
-        let base = u16::from(lo) | (u16::from(hi) << 8);
-
-        let y = self.registers.y as u16;
-        let addr = base.wrapping_add(y);
-
-        let page_cross = (base & 0xFF00) != (addr & 0xFF00);
-        match access {
-            Access::Read => {
-                if page_cross {
-                    self.clock_cycle();
-                }
-            }
-            Access::Write | Access::ReadModify => {
-                self.clock_cycle();
-            }
-        }
+    fn addr_absolute_x(&mut self, access: Access) -> u16 {
+        let base = self.addr_absolute();
+        self.addr_indexed(base, self.registers.x, access)
+    }
 
-        addr
+    fn addr_absolute_y(&mut self, access: Access) -> u16 {
+        let base = self.addr_absolute();
+        self.addr_indexed(base, self.registers.y, access)
     }
 
     fn addr_preindexed_indirect_zeropage_x(&mut self) -> u16 {
         let first_addr = self.next();
+        self.dummy_read(u16::from(first_addr));
         let first_addr = first_addr.wrapping_add(self.registers.x);
-        self.clock_cycle();
 
         let lo = self.read_memory(u16::from(first_addr));
         let hi = self.read_memory(u16::from(first_addr.wrapping_add(1)));
+        self.data_flags |= CDL_INDIRECT_DATA;
         u16::from(lo) | (u16::from(hi) << 8)
     }
 
@@ -271,83 +500,84 @@ impl Cpu {
         let hi = self.read_memory(u16::from(addr.wrapping_add(1)));
 
         let base = u16::from(lo) | (u16::from(hi) << 8);
-
-        let y = self.registers.y as u16;
-        let addr = base.wrapping_add(y);
-
-        let page_cross = (base & 0xFF00) != (addr & 0xFF00);
-        match access {
-            Access::Read => {
-                if page_cross {
-                    self.clock_cycle();
-                }
+        self.data_flags |= CDL_INDIRECT_DATA;
+        self.addr_indexed(base, self.registers.y, access)
+    }
+
+    /// Resolves the address of the operand, `access` decides about the dummy read of indexed modes
+    fn operand_address(&mut self, mode: AddressingMode, access: Access) -> u16 {
+        match mode {
+            AddressingMode::Zeropage => self.addr_zeropage(),
+            AddressingMode::ZeropageX => self.addr_zeropage_x(),
+            AddressingMode::ZeropageY => self.addr_zeropage_y(),
+            AddressingMode::Absolute => self.addr_absolute(),
+            AddressingMode::AbsoluteX => self.addr_absolute_x(access),
+            AddressingMode::AbsoluteY => self.addr_absolute_y(access),
+            AddressingMode::PreindexedIndirectZeropageX => {
+                self.addr_preindexed_indirect_zeropage_x()
             }
-            Access::Write | Access::ReadModify => {
-                self.clock_cycle();
+            AddressingMode::PostindexedIndirectZeropageY => {
+                self.addr_postindexed_indirect_zeropage_y(access)
             }
+            AddressingMode::IndirectZeropage => self.addr_indirect_zeropage(),
+            _ => unreachable!("{:?} has no operand address", mode),
         }
-
-        addr
-    }
-
-    fn read_zeropage(&mut self) -> u8 {
-        let addr = self.addr_zeropage();
-        self.read_memory(addr)
     }
 
-    fn read_zeropage_x(&mut self) -> u8 {
-        let addr = self.addr_zeropage_x();
-        self.read_memory(addr)
-    }
-
-    fn read_zeropage_y(&mut self) -> u8 {
-        let addr = self.addr_zeropage_y();
-        self.read_memory(addr)
-    }
-
-    fn read_absolute(&mut self) -> u8 {
-        let addr = self.addr_absolute();
-        self.read_memory(addr)
-    }
-
-    fn read_absolute_x(&mut self) -> u8 {
-        let addr = self.addr_absolute_x(Access::Read);
-        self.read_memory(addr)
+    fn read_operand(&mut self, mode: AddressingMode, operation: fn(&mut Self, u8)) {
+        let data = if mode == AddressingMode::Immediate {
+            self.next()
+        } else {
+            let addr = self.operand_address(mode, Access::Read);
+            self.read_memory(addr)
+        };
+        operation(self, data);
     }
 
-    fn read_absolute_y(&mut self) -> u8 {
-        let addr = self.addr_absolute_y(Access::Read);
-        self.read_memory(addr)
+    fn write_operand(&mut self, mode: AddressingMode, data: u8) {
+        let addr = self.operand_address(mode, Access::Write);
+        self.write_memory(addr, data);
     }
 
-    fn read_preindexed_indirect_zeropage_x(&mut self) -> u8 {
-        let addr = self.addr_preindexed_indirect_zeropage_x();
-        self.read_memory(addr)
+    fn modify_operand(
+        &mut self,
+        mode: AddressingMode,
+        access: Access,
+        operation: fn(&mut Self, u8) -> u8,
+    ) {
+        if mode == AddressingMode::Accumulator {
+            let result = operation(self, self.registers.a);
+            self.registers.update_a(result);
+            self.dummy_read_next();
+        } else {
+            let addr = self.operand_address(mode, access);
+            self.read_modify_write(addr, operation);
+        }
     }
 
-    fn read_postindexed_indirect_zeropage_y(&mut self) -> u8 {
-        let addr = self.addr_postindexed_indirect_zeropage_y(Access::Read);
-        self.read_memory(addr)
+    /// The 65C02 only spends the extra cycle of indexed shifts on a page crossing
+    fn shift_access(&self) -> Access {
+        if self.is_cmos() {
+            Access::Read
+        } else {
+            Access::ReadModify
+        }
     }
 
-    fn write_zeropage(&mut self, data: u8) {
-        let addr = self.addr_zeropage();
-        self.write_memory(addr, data);
-    }
-    fn write_zeropage_x(&mut self, data: u8) {
-        let addr = self.addr_zeropage_x();
-        self.write_memory(addr, data);
-    }
-    fn write_zeropage_y(&mut self, data: u8) {
-        let addr = self.addr_zeropage_y();
-        self.write_memory(addr, data);
-    }
-    fn write_absolute(&mut self, data: u8) {
-        let addr = self.addr_absolute();
-        self.write_memory(addr, data);
+    fn decimal_mode(&self) -> bool {
+        !self.options.ignore_decimal_bit && self.registers.get_decimal_bit()
     }
 
     fn adc(&mut self, m: u8) {
+        if self.decimal_mode() {
+            if self.is_cmos() {
+                self.adc_decimal_cmos(m);
+            } else {
+                self.adc_decimal_nmos(m);
+            }
+            return;
+        }
+
         let a = self.registers.a;
         let c = if self.registers.get_carry_bit() { 1 } else { 0 };
 
@@ -356,60 +586,76 @@ impl Cpu {
 
         let v = (!(a ^ m) & (a ^ result) & 0x80) != 0;
 
-        if !self.options.ignore_decimal_bit && self.registers.get_decimal_bit() {
-            let mut adj = 0u16;
-            if ((a & 0x0F) as u16 + (m & 0x0F) as u16 + c) > 9 {
-                adj += 0x06;
-            }
-            if sum > 0x99 {
-                adj += 0x60;
-            }
-            let bcd = result.wrapping_add(adj as u8);
+        self.registers.update_carry_bit(sum > 0xFF);
+        self.registers.update_overflow_bit(v);
+        self.registers.update_a(result);
+    }
 
-            let carry = sum > 0x99;
+    /// Decimal addition according to http://www.6502.org/tutorials/decimal_mode.html (sequences 1 and 2),
+    /// also defined for invalid BCD operands
+    fn adc_decimal_nmos(&mut self, m: u8) {
+        let a = self.registers.a;
+        let c = if self.registers.get_carry_bit() { 1 } else { 0 };
 
-            self.registers.update_carry_bit(carry);
-            self.registers.update_overflow_bit(v);
-            self.registers.update_a(bcd);
-        } else {
-            self.registers.update_carry_bit(sum > 0xFF);
-            self.registers.update_overflow_bit(v);
-            self.registers.update_a(result);
+        let mut lo = (a & 0x0F) as u16 + (m & 0x0F) as u16 + c;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) as u16 + (m & 0xF0) as u16 + lo;
+        let signed_sum = (a & 0xF0) as i8 as i16 + (m & 0xF0) as i8 as i16 + lo as i16;
+        if sum >= 0xA0 {
+            sum += 0x60;
         }
+
+        self.registers.update_carry_bit(sum >= 0x100);
+        self.registers
+            .update_overflow_bit(!(-128..=127).contains(&signed_sum));
+        // N is taken before the high nibble is adjusted and Z from the binary sum
+        self.registers.update_negative_bit(signed_sum & 0x80 != 0);
+        self.registers
+            .update_zero_bit(a.wrapping_add(m).wrapping_add(c as u8) == 0);
+        self.registers.a = sum as u8;
     }
 
     fn sbc(&mut self, m: u8) {
         let a = self.registers.a;
         let c = if self.registers.get_carry_bit() { 1 } else { 0 };
 
+        if self.decimal_mode() && self.is_cmos() {
+            self.sbc_decimal_cmos(m);
+            return;
+        }
+
         let diff = a as i16 - m as i16 - (1 - c);
         let result = diff as u8;
 
         let v = ((a ^ m) & (a ^ result) & 0x80) != 0;
 
-        if self.registers.get_decimal_bit() {
-            let mut adj = 0i16;
+        // the NMOS 6502 only adjusts the accumulator, all flags are those of the binary subtraction
+        let bcd = self.decimal_mode().then(|| self.sbc_decimal_nmos(m));
 
-            if ((a & 0x0F) as i16) - ((m & 0x0F) as i16) - (1 - c) < 0 {
-                adj -= 0x06;
-            }
-
-            if diff < 0 {
-                adj -= 0x60;
-            }
-
-            let bcd = (result as i16).wrapping_add(adj) as u8;
+        self.registers.update_carry_bit(diff >= 0);
+        self.registers.update_overflow_bit(v);
+        self.registers.update_a(result);
+        if let Some(bcd) = bcd {
+            self.registers.a = bcd;
+        }
+    }
 
-            let carry = diff >= 0;
+    /// Decimal subtraction according to http://www.6502.org/tutorials/decimal_mode.html (sequence 3)
+    fn sbc_decimal_nmos(&self, m: u8) -> u8 {
+        let a = self.registers.a;
+        let c = if self.registers.get_carry_bit() { 1 } else { 0 };
 
-            self.registers.update_carry_bit(carry);
-            self.registers.update_overflow_bit(v);
-            self.registers.update_a(bcd);
-        } else {
-            self.registers.update_carry_bit(diff >= 0);
-            self.registers.update_overflow_bit(v);
-            self.registers.update_a(result);
+        let mut lo = (a & 0x0F) as i16 - (m & 0x0F) as i16 + c - 1;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+        let mut result = (a & 0xF0) as i16 - (m & 0xF0) as i16 + lo;
+        if result < 0 {
+            result -= 0x60;
         }
+        result as u8
     }
 
     fn ora(&mut self, data: u8) {
@@ -505,16 +751,69 @@ impl Cpu {
             .update_overflow_bit(data & (1 << STATUS_OVERFLOW_BIT) != 0);
     }
 
+    fn read_modify_write(&mut self, addr: u16, operation: fn(&mut Self, u8) -> u8) {
+        let data = self.read_memory(addr);
+        // the unmodified value is written back while the operation is performed
+        if self.is_cmos() {
+            self.dummy_read(addr);
+        } else {
+            self.write_memory(addr, data);
+        }
+        let result = operation(self, data);
+        self.write_memory(addr, result);
+    }
+
+    fn slo(&mut self, data: u8) -> u8 {
+        let result = self.asl(data);
+        self.ora(result);
+        result
+    }
+
+    fn rla(&mut self, data: u8) -> u8 {
+        let result = self.rol(data);
+        self.and(result);
+        result
+    }
+
+    fn sre(&mut self, data: u8) -> u8 {
+        let result = self.lsr(data);
+        self.eor(result);
+        result
+    }
+
+    fn rra(&mut self, data: u8) -> u8 {
+        let result = self.ror(data);
+        self.adc(result);
+        result
+    }
+
+    fn dcp(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_sub(1);
+        self.cmp(result);
+        result
+    }
+
+    fn isb(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_add(1);
+        self.sbc(result);
+        result
+    }
+
+    fn lax(&mut self, data: u8) {
+        self.registers.update_a(data);
+        self.registers.update_x(data);
+    }
+
     fn branch_on_condition(&mut self, cond: bool) {
         let offset = self.next() as i8;
         if cond {
-            self.clock_cycle();
+            self.dummy_read_next();
 
             let old = self.registers.pc;
             let new = (old as i16 + offset as i16) as u16;
 
             if (old ^ new) & 0xFF00 != 0 {
-                self.clock_cycle();
+                self.dummy_read((old & 0xFF00) | (new & 0x00FF));
             }
 
             self.registers.pc = new;
@@ -532,431 +831,90 @@ impl Cpu {
     }
 }
 
-impl Cpu {
-    fn nop(&mut self) {}
-
-    fn adc_immediate(&mut self) {
-        let data = self.next();
-        self.adc(data);
-    }
-    fn adc_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.adc(data);
-    }
-    fn adc_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.adc(data);
-    }
-    fn adc_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.adc(data);
-    }
-    fn adc_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.adc(data);
-    }
-    fn adc_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.adc(data);
-    }
-    fn adc_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.adc(data);
-    }
-    fn adc_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.adc(data);
+impl<B: CpuBus> Cpu<B> {
+    fn nop(&mut self) {
+        self.dummy_read_next();
     }
 
-    fn sbc_immediate(&mut self) {
-        let data = self.next();
-        self.sbc(data);
-    }
-    fn sbc_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.sbc(data);
-    }
-    fn sbc_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.sbc(data);
-    }
-    fn sbc_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.sbc(data);
-    }
-    fn sbc_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.sbc(data);
-    }
-    fn sbc_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.sbc(data);
-    }
-    fn sbc_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.sbc(data);
+    fn jmp_absolute(&mut self) {
+        let lo = self.next();
+        let hi = self.next();
+        self.registers.pc = u16::from(lo) | (u16::from(hi) << 8);
     }
-    fn sbc_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.sbc(data);
+    fn jmp_indirect(&mut self) {
+        let addr_lo = self.next();
+        let addr_hi = self.next();
+        let addr = u16::from(addr_lo) | (u16::from(addr_hi) << 8);
+        let data_lo = self.read_memory(addr);
+        // page crossing bug
+        let data_hi = if self.is_cmos() {
+            // fixed on the 65C02 at the cost of an additional cycle
+            self.dummy_read(self.registers.pc.wrapping_sub(1));
+            self.read_memory(addr.wrapping_add(1))
+        } else if addr_lo == 0xff {
+            self.read_memory(addr & 0xff00)
+        } else {
+            self.read_memory(addr.wrapping_add(1))
+        };
+        let data = u16::from(data_lo) | (u16::from(data_hi) << 8);
+        self.registers.pc = data;
+        self.jumped_indirectly = true;
     }
 
-    fn eor_immediate(&mut self) {
-        let data = self.next();
-        self.eor(data);
-    }
-    fn eor_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.eor(data);
-    }
-    fn eor_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.eor(data);
-    }
-    fn eor_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.eor(data);
-    }
-    fn eor_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.eor(data);
-    }
-    fn eor_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.eor(data);
-    }
-    fn eor_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.eor(data);
-    }
-    fn eor_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.eor(data);
-    }
+    fn jsr(&mut self) {
+        let target_lo = self.next();
+        self.dummy_read_stack();
 
-    fn and_immediate(&mut self) {
-        let data = self.next();
-        self.and(data);
-    }
-    fn and_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.and(data);
-    }
-    fn and_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.and(data);
-    }
-    fn and_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.and(data);
-    }
-    fn and_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.and(data);
-    }
-    fn and_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.and(data);
-    }
-    fn and_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.and(data);
-    }
-    fn and_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.and(data);
-    }
-
-    fn ora_immediate(&mut self) {
-        let data = self.next();
-        self.ora(data);
-    }
-    fn ora_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.ora(data);
-    }
-    fn ora_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.ora(data);
-    }
-    fn ora_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.ora(data);
-    }
-    fn ora_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.ora(data);
-    }
-    fn ora_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.ora(data);
-    }
-    fn ora_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.ora(data);
-    }
-    fn ora_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.ora(data);
-    }
-
-    fn lda_immediate(&mut self) {
-        let data = self.next();
-        self.registers.update_a(data);
-    }
-    fn lda_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.registers.update_a(data);
-    }
-    fn lda_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.registers.update_a(data);
-    }
-    fn lda_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.registers.update_a(data);
-    }
-    fn lda_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.registers.update_a(data);
-    }
-    fn lda_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.registers.update_a(data);
-    }
-    fn lda_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.registers.update_a(data);
-    }
-    fn lda_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.registers.update_a(data);
-    }
-
-    fn ldx_immediate(&mut self) {
-        let data = self.next();
-        self.registers.update_x(data);
-    }
-    fn ldx_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.registers.update_x(data);
-    }
-    fn ldx_zeropage_y(&mut self) {
-        let data = self.read_zeropage_y();
-        self.registers.update_x(data);
-    }
-    fn ldx_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.registers.update_x(data);
-    }
-    fn ldx_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.registers.update_x(data);
-    }
-
-    fn ldy_immediate(&mut self) {
-        let data = self.next();
-        self.registers.update_y(data);
-    }
-    fn ldy_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.registers.update_y(data);
-    }
-    fn ldy_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.registers.update_y(data);
-    }
-    fn ldy_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.registers.update_y(data);
-    }
-    fn ldy_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.registers.update_y(data);
-    }
-
-    fn sta_zeropage(&mut self) {
-        self.write_zeropage(self.registers.a);
-    }
-    fn sta_zeropage_x(&mut self) {
-        self.write_zeropage_x(self.registers.a);
-    }
-    fn sta_absolute(&mut self) {
-        self.write_absolute(self.registers.a);
-    }
-    fn sta_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::Write);
-        self.write_memory(addr, self.registers.a);
-    }
-    fn sta_absolute_y(&mut self) {
-        let addr = self.addr_absolute_y(Access::Write);
-        self.write_memory(addr, self.registers.a);
-    }
-    fn sta_preindexed_indirect_zeropage_x(&mut self) {
-        let addr = self.addr_preindexed_indirect_zeropage_x();
-        self.write_memory(addr, self.registers.a);
-    }
-    fn sta_postindexed_indirect_zeropage_y(&mut self) {
-        let addr = self.addr_postindexed_indirect_zeropage_y(Access::Write);
-        self.write_memory(addr, self.registers.a);
-    }
-
-    fn stx_zeropage(&mut self) {
-        self.write_zeropage(self.registers.x);
-    }
-    fn stx_zeropage_y(&mut self) {
-        self.write_zeropage_y(self.registers.x);
-    }
-    fn stx_absolute(&mut self) {
-        self.write_absolute(self.registers.x);
-    }
-
-    fn sty_zeropage(&mut self) {
-        self.write_zeropage(self.registers.y);
-    }
-    fn sty_zeropage_x(&mut self) {
-        self.write_zeropage_x(self.registers.y);
-    }
-    fn sty_absolute(&mut self) {
-        self.write_absolute(self.registers.y);
-    }
-
-    fn jmp_absolute(&mut self) {
-        let instr_addr = self.registers.pc - 1;
-        let lo = self.next();
-        let hi = self.next();
-        let data = u16::from(lo) | (u16::from(hi) << 8);
-        if data == instr_addr {
-            panic!("Trapped at {:#x}", instr_addr);
-        }
-        self.registers.pc = data;
-    }
-    fn jmp_indirect(&mut self) {
-        let addr_lo = self.next();
-        let addr_hi = self.next();
-        let addr = u16::from(addr_lo) | (u16::from(addr_hi) << 8);
-        let data_lo = self.read_memory(addr);
-        // page crossing bug
-        let data_hi = if addr_lo == 0xff {
-            self.read_memory(addr & 0xff00)
-        } else {
-            self.read_memory(addr.wrapping_add(1))
-        };
-        let data = u16::from(data_lo) | (u16::from(data_hi) << 8);
-        self.registers.pc = data;
-    }
-
-    fn jsr(&mut self) {
-        let target_lo = self.next();
-        let target_hi = self.next();
-        let target_addr = u16::from(target_lo) | (u16::from(target_hi) << 8);
-
-        let ret = self.registers.pc.wrapping_sub(1);
+        // the pushed return address points to the high byte of the target address
+        let ret = self.registers.pc;
         let ret_lo = (ret & 0x00FF) as u8;
         let ret_hi = (ret >> 8) as u8;
 
         self.push_stack(ret_hi);
         self.push_stack(ret_lo);
 
-        self.clock_cycle();
+        let target_hi = self.fetch(self.registers.pc);
+        let target_addr = u16::from(target_lo) | (u16::from(target_hi) << 8);
         self.registers.pc = target_addr;
+        self.profile_call(self.registers.s.wrapping_add(2));
     }
 
     fn rts(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
+        self.dummy_read_stack();
 
         let ret_lo = self.pull_stack();
         let ret_hi = self.pull_stack();
         let ret_addr = u16::from(ret_lo) | (u16::from(ret_hi) << 8);
 
-        self.registers.pc = ret_addr.wrapping_add(1);
-        self.clock_cycle();
-
-        self.clock_cycle();
-    }
-
-    fn cmp_immediate(&mut self) {
-        let data = self.next();
-        self.cmp(data);
-    }
-    fn cmp_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.cmp(data);
-    }
-    fn cmp_zeropage_x(&mut self) {
-        let data = self.read_zeropage_x();
-        self.cmp(data);
-    }
-    fn cmp_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.cmp(data);
-    }
-    fn cmp_absolute_x(&mut self) {
-        let data = self.read_absolute_x();
-        self.cmp(data);
-    }
-    fn cmp_absolute_y(&mut self) {
-        let data = self.read_absolute_y();
-        self.cmp(data);
-    }
-    fn cmp_preindexed_indirect_zeropage_x(&mut self) {
-        let data = self.read_preindexed_indirect_zeropage_x();
-        self.cmp(data);
-    }
-    fn cmp_postindexed_indirect_zeropage_y(&mut self) {
-        let data = self.read_postindexed_indirect_zeropage_y();
-        self.cmp(data);
-    }
-
-    fn cpx_immediate(&mut self) {
-        let data = self.next();
-        self.cpx(data);
-    }
-    fn cpx_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.cpx(data);
-    }
-    fn cpx_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.cpx(data);
-    }
-
-    fn cpy_immediate(&mut self) {
-        let data = self.next();
-        self.cpy(data);
-    }
-    fn cpy_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.cpy(data);
-    }
-    fn cpy_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.cpy(data);
+        self.registers.pc = ret_addr;
+        self.next();
+        self.profile_return();
     }
 
     fn tax(&mut self) {
         self.registers.update_x(self.registers.a);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn tay(&mut self) {
         self.registers.update_y(self.registers.a);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn tsx(&mut self) {
         self.registers.update_x(self.registers.s);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn txa(&mut self) {
         self.registers.update_a(self.registers.x);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn txs(&mut self) {
         self.registers.s = self.registers.x;
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn tya(&mut self) {
         self.registers.update_a(self.registers.y);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn bcc(&mut self) {
@@ -986,72 +944,74 @@ impl Cpu {
 
     fn clc(&mut self) {
         self.registers.update_carry_bit(false);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn cld(&mut self) {
         self.registers.update_decimal_bit(false);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn cli(&mut self) {
-        self.registers.update_interupt_bit(false);
-        self.clock_cycle();
+        self.registers.update_interrupt_bit(false);
+        self.dummy_read_next();
     }
     fn clv(&mut self) {
         self.registers.update_overflow_bit(false);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn sec(&mut self) {
         self.registers.update_carry_bit(true);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn sed(&mut self) {
         self.registers.update_decimal_bit(true);
-        self.clock_cycle();
+        self.dummy_read_next();
     }
     fn sei(&mut self) {
-        self.registers.update_interupt_bit(true);
-        self.clock_cycle();
+        self.registers.update_interrupt_bit(true);
+        self.dummy_read_next();
     }
 
     fn dex(&mut self) {
         self.registers.update_x(self.registers.x.wrapping_sub(1));
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn dey(&mut self) {
         self.registers.update_y(self.registers.y.wrapping_sub(1));
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn inx(&mut self) {
         self.registers.update_x(self.registers.x.wrapping_add(1));
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn iny(&mut self) {
         self.registers.update_y(self.registers.y.wrapping_add(1));
-        self.clock_cycle();
+        self.dummy_read_next();
     }
 
     fn pha(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
         self.push_stack(self.registers.a);
     }
 
     fn php(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
         self.push_stack(self.registers.p | (1 << STATUS_BREAK_BIT) | (1 << STATUS_IGNORED_BIT));
     }
 
     fn pla(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
+        self.dummy_read_stack();
         let data = self.pull_stack();
         self.registers.update_a(data);
     }
 
     fn plp(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
+        self.dummy_read_stack();
         let data = self.pull_stack();
         self.registers.p =
             (data & !STATUS_BREAK_IGNORED_MASK) | (self.registers.p & STATUS_BREAK_IGNORED_MASK);
@@ -1067,15 +1027,20 @@ impl Cpu {
         self.push_stack(ret_lo);
         self.push_stack(self.registers.p | (1 << STATUS_BREAK_BIT) | (1 << STATUS_IGNORED_BIT));
 
-        self.registers.update_interupt_bit(true);
+        self.registers.update_interrupt_bit(true);
+        if self.is_cmos() {
+            self.registers.update_decimal_bit(false);
+        }
 
         let pc_lo = self.read_memory(INTERRUPT_VECTOR_IRQ_LO);
         let pc_hi = self.read_memory(INTERRUPT_VECTOR_IRQ_HI);
         self.registers.pc = u16::from(pc_lo) | (u16::from(pc_hi) << 8);
+        self.profile_call(self.registers.s.wrapping_add(3));
     }
 
     fn rti(&mut self) {
-        self.clock_cycle();
+        self.dummy_read_next();
+        self.dummy_read_stack();
 
         self.registers.p = (self.pull_stack() & !STATUS_BREAK_IGNORED_MASK)
             | (self.registers.p & STATUS_BREAK_IGNORED_MASK);
@@ -1083,499 +1048,987 @@ impl Cpu {
         let ret_lo = self.pull_stack();
         let ret_hi = self.pull_stack();
         self.registers.pc = u16::from(ret_lo) | (u16::from(ret_hi) << 8);
-
-        self.clock_cycle();
+        self.profile_return();
     }
+}
 
-    fn bit_zeropage(&mut self) {
-        let data = self.read_zeropage();
-        self.bit(data);
+// Unofficial opcodes
+// (see https://www.nesdev.org/wiki/CPU_unofficial_opcodes)
+impl<B: CpuBus> Cpu<B> {
+    fn las(&mut self, data: u8) {
+        let data = data & self.registers.s;
+        self.registers.s = data;
+        self.lax(data);
     }
 
-    fn bit_absolute(&mut self) {
-        let data = self.read_absolute();
-        self.bit(data);
+    fn anc(&mut self, data: u8) {
+        self.and(data);
+        self.registers
+            .update_carry_bit(self.registers.get_negative_bit());
     }
 
-    fn asl_accumulator(&mut self) {
-        let result = self.asl(self.registers.a);
+    fn alr(&mut self, data: u8) {
+        self.and(data);
+        let result = self.lsr(self.registers.a);
         self.registers.update_a(result);
     }
-    fn asl_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.asl(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
-    }
-    fn asl_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.asl(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
-    }
-    fn asl_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.asl(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
-    }
-    fn asl_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.asl(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
-    }
 
-    fn lsr_accumulator(&mut self) {
-        let result = self.lsr(self.registers.a);
+    fn arr(&mut self, data: u8) {
+        self.and(data);
+        let result = self.ror(self.registers.a);
         self.registers.update_a(result);
+        // carry and overflow are taken from bit 6 and bit 6 xor bit 5 of the result
+        self.registers.update_carry_bit(result & 0x40 != 0);
+        self.registers
+            .update_overflow_bit(((result >> 6) ^ (result >> 5)) & 1 != 0);
     }
-    fn lsr_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.lsr(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    fn axs(&mut self, data: u8) {
+        let (result, borrow) = (self.registers.a & self.registers.x).overflowing_sub(data);
+        self.registers.update_carry_bit(!borrow);
+        self.registers.update_x(result);
     }
-    fn lsr_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.lsr(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+}
+
+impl Cpu<Bus> {
+    pub fn with_nes_options(memory: Bus) -> Self {
+        Self::new(memory, NES_CPU_OPTIONS)
     }
-    fn lsr_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.lsr(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn poll_new_frame(&mut self) -> bool {
+        let new_frame = self.bus.poll_new_frame();
+        if new_frame {
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.end_frame();
+            }
+        }
+        new_frame
     }
-    fn lsr_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.lsr(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+}
+
+impl<B: CpuBus> Cpu<B> {
+    /// Turns the whole system off and on again
+    pub fn power_on(&mut self) {
+        self.bus.power_on();
+        self.invalidate_block_cache();
+        self.registers = Registers::new();
+        self.registers.p = 1 << STATUS_IGNORED_BIT;
+        self.cycle = 0;
+        self.reset_sequence();
     }
 
-    fn rol_accumulator(&mut self) {
-        let result = self.rol(self.registers.a);
-        self.registers.update_a(result);
+    /// Presses the reset button, the registers keep their values except for S and the I flag
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.reset_sequence();
     }
-    fn rol_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.rol(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    fn reset_sequence(&mut self) {
+        // the reset sequence takes 7 cycles like an interrupt, but the pushes to the stack are
+        // turned into reads
+        for _ in 0..5 {
+            self.clock_cycle();
+        }
+        self.registers.s = self.registers.s.wrapping_sub(3);
+        self.registers.update_interrupt_bit(true);
+        let pc_lo = self.read_memory(INTERRUPT_VECTOR_RES_LO);
+        let pc_hi = self.read_memory(INTERRUPT_VECTOR_RES_HI);
+        self.registers.pc = u16::from(pc_lo) | (u16::from(pc_hi) << 8);
+        self.irq_inhibited = true;
+        self.waiting_for_interrupt = false;
+        self.stopped = false;
+        self.halted = None;
+    }
+
+    /// Decodes the instruction at `addr` using the current index registers
+    pub fn disassemble(&self, addr: u16) -> Disassembly {
+        disassemble(
+            &self.bus,
+            self.options.variant,
+            addr,
+            self.registers.x,
+            self.registers.y,
+        )
     }
-    fn rol_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.rol(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn new(memory: B, options: CpuOptions) -> Self {
+        Self {
+            options,
+            registers: Registers::new(),
+            bus: memory,
+            cycle: 0,
+            irq_inhibited: true,
+            waiting_for_interrupt: false,
+            stopped: false,
+            halted: None,
+            tracer: None,
+            profiler: None,
+            block_cache: None,
+            decoded: None,
+            breakpoints: Breakpoints::default(),
+            break_hit: None,
+            resumed_at: None,
+            instruction_pc: 0,
+            code_flags: CDL_CODE,
+            data_flags: CDL_DATA,
+            jumped_indirectly: false,
+        }
     }
-    fn rol_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.rol(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
     }
-    fn rol_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.rol(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
     }
 
-    fn ror_accumulator(&mut self) {
-        let result = self.ror(self.registers.a);
-        self.registers.update_a(result);
+    /// CPU cycles since the CPU was created
+    pub fn cycle(&self) -> u64 {
+        self.cycle
     }
-    fn ror_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.ror(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn pending_interrupts(&self) -> PendingInterrupts {
+        PendingInterrupts {
+            nmi: self.bus.nmi_pending(),
+            irq: self.bus.poll_irq(),
+        }
     }
-    fn ror_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.ror(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
     }
-    fn ror_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.ror(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
     }
-    fn ror_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.ror(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    /// The condition that paused the CPU, `tick` does nothing until `resume` is called
+    pub fn break_hit(&self) -> Option<&BreakHit> {
+        self.break_hit.as_ref()
     }
 
-    fn inc_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.inc(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+    pub fn resume(&mut self) {
+        // don't stop at the execute breakpoint we are paused on again
+        self.resumed_at = match self.break_hit.take() {
+            Some(hit) if hit.breakpoint == Breakpoint::Execute(self.registers.pc) => {
+                Some(self.registers.pc)
+            }
+            _ => None,
+        };
     }
-    fn inc_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.inc(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    fn break_on(&mut self, breakpoint: Breakpoint, addr: u16, value: Option<u8>) {
+        if self.break_hit.is_none() {
+            self.break_hit = Some(BreakHit::new(
+                breakpoint,
+                self.instruction_pc,
+                addr,
+                value,
+                &self.registers,
+            ));
+        }
     }
-    fn inc_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.inc(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
     }
-    fn inc_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.inc(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
     }
 
-    fn dec_zeropage(&mut self) {
-        let addr = self.addr_zeropage();
-        let data = self.read_memory(addr);
-        let result = self.dec(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+    /// Executes instructions from a cache of decoded basic blocks instead of fetching them from
+    /// the bus, disabling it drops the cache
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.block_cache = enabled.then(BlockCache::default);
     }
-    fn dec_zeropage_x(&mut self) {
-        let addr = self.addr_zeropage_x();
-        let data = self.read_memory(addr);
-        let result = self.dec(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    /// Only writes by the CPU are noticed by the block cache, this has to be called after writing
+    /// to the bus from outside
+    pub fn invalidate_block_cache(&mut self) {
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.invalidate();
+        }
     }
-    fn dec_absolute(&mut self) {
-        let addr = self.addr_absolute();
-        let data = self.read_memory(addr);
-        let result = self.dec(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
     }
-    fn dec_absolute_x(&mut self) {
-        let addr = self.addr_absolute_x(Access::ReadModify);
-        let data = self.read_memory(addr);
-        let result = self.dec(data);
-        self.clock_cycle();
-        self.write_memory(addr, result);
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
     }
-}
 
-impl Cpu {
+    /// `stack` is the stack pointer before the return address was pushed
+    fn profile_call(&mut self, stack: u8) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enter(self.registers.pc, stack, self.cycle);
+        }
+    }
 
-    pub fn reset(&mut self) {
-        self.registers.a = 0;
-        self.registers.x = 0;
-        self.registers.y = 0;
-        self.registers.s = 0xfd;
-        self.registers.p = 0b100100;
-        self.registers.pc = self.bus.reset_vector();
+    fn profile_return(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.leave(self.registers.s, self.cycle);
+        }
     }
 
-    pub fn with_nes_options(memory: Bus, clock_speed: u32) -> Self {
-        Self {
-            options: NES_CPU_OPTIONS,
-            registers: Registers::new(),
-            bus: memory,
-            cycle: 0,
-            clock_speed,
+    /// Formats the current state like a line of a nestest/Nintendulator log
+    pub fn trace_line(&self) -> String {
+        let disassembly = self.disassemble(self.registers.pc);
+        let bytes: Vec<String> = disassembly
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        // unofficial opcodes are marked by a * in front of the mnemonic
+        let marker = if disassembly.opcode.official { " " } else { "" };
+        let mut line = format!(
+            "{:04X}  {:<8} {:<32} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            disassembly.addr,
+            bytes.join(" "),
+            format!("{}{}", marker, disassembly),
+            self.registers.a,
+            self.registers.x,
+            self.registers.y,
+            self.registers.p,
+            self.registers.s,
+        );
+        if let Some((scanline, dot)) = self.bus.ppu_position() {
+            line += &format!(" PPU:{:3},{:3}", scanline, dot);
         }
+        line + &format!(" CYC:{}", self.cycle)
     }
 
-    pub fn tick(&mut self) {
-        if self.bus.poll_nmi() {
-            self.interrupt_nmi();
+    /// Executes the next instruction or interrupt, fails while the CPU is halted by an error
+    pub fn tick(&mut self) -> Result<(), CpuError> {
+        if let Some(error) = self.halted {
+            return Err(error);
+        }
+
+        if self.break_hit.is_some() {
+            return Ok(());
+        }
+
+        if self.stopped {
+            self.clock_cycle();
+            return Ok(());
+        }
+
+        let nmi = self.bus.poll_nmi();
+        let irq = self.bus.poll_irq();
+        if self.waiting_for_interrupt {
+            if !nmi && !irq {
+                self.clock_cycle();
+                return Ok(());
+            }
+            self.waiting_for_interrupt = false;
         }
-        self.step();
-        self.bus.tick(self.cycle);
+
+        if nmi {
+            self.interrupt(INTERRUPT_VECTOR_NMI_LO, INTERRUPT_VECTOR_NMI_HI);
+        } else if irq && !self.irq_inhibited {
+            self.interrupt(INTERRUPT_VECTOR_IRQ_LO, INTERRUPT_VECTOR_IRQ_HI);
+        }
+        self.step()
     }
 
-    pub fn poll_new_frame(&mut self) -> bool {
-        self.bus.poll_new_frame()
+    /// The error that halted the CPU, it stays halted until the next reset
+    pub fn halted(&self) -> Option<CpuError> {
+        self.halted
     }
 
-    fn interrupt_nmi(&mut self) {
+    fn halt(&mut self, error: CpuError) {
+        self.halted = Some(error);
+        // leave the PC on the offending opcode for debuggers
+        self.registers.pc = self.instruction_pc;
+    }
+
+    fn interrupt(&mut self, vector_lo: u16, vector_hi: u16) {
+        // the opcode fetch is discarded and followed by another dummy read
+        self.dummy_read_next();
+        self.dummy_read_next();
+
         self.push_stack((self.registers.pc >> 8) as u8);
         self.push_stack((self.registers.pc & 0xFF) as u8);
+        // hardware interrupts push the status with the break bit cleared
+        self.push_stack((self.registers.p & !(1 << STATUS_BREAK_BIT)) | (1 << STATUS_IGNORED_BIT));
 
-        self.registers.update_status_bit(STATUS_BREAK_BIT, true);
-        self.registers.update_status_bit(STATUS_IGNORED_BIT, true);
-        self.push_stack(self.registers.p);
+        self.registers.update_interrupt_bit(true);
+        if self.is_cmos() {
+            self.registers.update_decimal_bit(false);
+        }
 
-        self.registers.update_interupt_bit(true);
-        self.registers.pc = self.interrupt_vector();
+        let pc_lo = self.read_memory(vector_lo);
+        let pc_hi = self.read_memory(vector_hi);
+        self.registers.pc = u16::from(pc_lo) | (u16::from(pc_hi) << 8);
+        self.profile_call(self.registers.s.wrapping_add(3));
     }
 
-    fn interrupt_vector(&mut self) -> u16 {
-        let hi = self.read_memory(INTERRUPT_VECTOR_NMI_HI) as u16;
-        let lo = self.read_memory(INTERRUPT_VECTOR_NMI_LO) as u16;
-        (hi << 8) | lo
-    }
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        if let Some(error) = self.halted {
+            return Err(error);
+        }
 
-    pub fn step(&mut self) {
-        // println!("{}", self.registers);
-        let instruction = self.next();
-        // println!("interpreting {instruction:2X}");
-        // println!();
-
-        match instruction {
-            0xEA => self.nop(),
-            0x69 => self.adc_immediate(),
-            0x65 => self.adc_zeropage(),
-            0x75 => self.adc_zeropage_x(),
-            0x6D => self.adc_absolute(),
-            0x7D => self.adc_absolute_x(),
-            0x79 => self.adc_absolute_y(),
-            0x61 => self.adc_preindexed_indirect_zeropage_x(),
-            0x71 => self.adc_postindexed_indirect_zeropage_y(),
-
-            0xE9 => self.sbc_immediate(),
-            0xE5 => self.sbc_zeropage(),
-            0xF5 => self.sbc_zeropage_x(),
-            0xED => self.sbc_absolute(),
-            0xFD => self.sbc_absolute_x(),
-            0xF9 => self.sbc_absolute_y(),
-            0xE1 => self.sbc_preindexed_indirect_zeropage_x(),
-            0xF1 => self.sbc_postindexed_indirect_zeropage_y(),
-
-            0x29 => self.and_immediate(),
-            0x25 => self.and_zeropage(),
-            0x35 => self.and_zeropage_x(),
-            0x2D => self.and_absolute(),
-            0x3D => self.and_absolute_x(),
-            0x39 => self.and_absolute_y(),
-            0x21 => self.and_preindexed_indirect_zeropage_x(),
-            0x31 => self.and_postindexed_indirect_zeropage_y(),
-
-            0x09 => self.ora_immediate(),
-            0x05 => self.ora_zeropage(),
-            0x15 => self.ora_zeropage_x(),
-            0x0D => self.ora_absolute(),
-            0x1D => self.ora_absolute_x(),
-            0x19 => self.ora_absolute_y(),
-            0x01 => self.ora_preindexed_indirect_zeropage_x(),
-            0x11 => self.ora_postindexed_indirect_zeropage_y(),
-
-            0x49 => self.eor_immediate(),
-            0x45 => self.eor_zeropage(),
-            0x55 => self.eor_zeropage_x(),
-            0x4D => self.eor_absolute(),
-            0x5D => self.eor_absolute_x(),
-            0x59 => self.eor_absolute_y(),
-            0x41 => self.eor_preindexed_indirect_zeropage_x(),
-            0x51 => self.eor_postindexed_indirect_zeropage_y(),
-
-            0x4C => self.jmp_absolute(),
-            0x6C => self.jmp_indirect(),
-
-            0x20 => self.jsr(),
-            0x60 => self.rts(),
-
-            0xA9 => self.lda_immediate(),
-            0xA5 => self.lda_zeropage(),
-            0xB5 => self.lda_zeropage_x(),
-            0xAD => self.lda_absolute(),
-            0xBD => self.lda_absolute_x(),
-            0xB9 => self.lda_absolute_y(),
-            0xA1 => self.lda_preindexed_indirect_zeropage_x(),
-            0xB1 => self.lda_postindexed_indirect_zeropage_y(),
-
-            0xA2 => self.ldx_immediate(),
-            0xA6 => self.ldx_zeropage(),
-            0xB6 => self.ldx_zeropage_y(),
-            0xAE => self.ldx_absolute(),
-            0xBE => self.ldx_absolute_y(),
-
-            0xA0 => self.ldy_immediate(),
-            0xA4 => self.ldy_zeropage(),
-            0xB4 => self.ldy_zeropage_x(),
-            0xAC => self.ldy_absolute(),
-            0xBC => self.ldy_absolute_x(),
-
-            0x85 => self.sta_zeropage(),
-            0x95 => self.sta_zeropage_x(),
-            0x8D => self.sta_absolute(),
-            0x9D => self.sta_absolute_x(),
-            0x99 => self.sta_absolute_y(),
-            0x81 => self.sta_preindexed_indirect_zeropage_x(),
-            0x91 => self.sta_postindexed_indirect_zeropage_y(),
-
-            0x86 => self.stx_zeropage(),
-            0x96 => self.stx_zeropage_y(),
-            0x8E => self.stx_absolute(),
-
-            0x84 => self.sty_zeropage(),
-            0x94 => self.sty_zeropage_x(),
-            0x8C => self.sty_absolute(),
-
-            0xC9 => self.cmp_immediate(),
-            0xC5 => self.cmp_zeropage(),
-            0xD5 => self.cmp_zeropage_x(),
-            0xCD => self.cmp_absolute(),
-            0xDD => self.cmp_absolute_x(),
-            0xD9 => self.cmp_absolute_y(),
-            0xC1 => self.cmp_preindexed_indirect_zeropage_x(),
-            0xD1 => self.cmp_postindexed_indirect_zeropage_y(),
-
-            0xE0 => self.cpx_immediate(),
-            0xE4 => self.cpx_zeropage(),
-            0xEC => self.cpx_absolute(),
-
-            0xC0 => self.cpy_immediate(),
-            0xC4 => self.cpy_zeropage(),
-            0xCC => self.cpy_absolute(),
-
-            0xAA => self.tax(),
-            0xA8 => self.tay(),
-            0xBA => self.tsx(),
-            0x8A => self.txa(),
-            0x9A => self.txs(),
-            0x98 => self.tya(),
-
-            0x90 => self.bcc(),
-            0xB0 => self.bcs(),
-            0xF0 => self.beq(),
-            0x30 => self.bmi(),
-            0xD0 => self.bne(),
-            0x10 => self.bpl(),
-            0x50 => self.bvc(),
-            0x70 => self.bvs(),
-
-            0x18 => self.clc(),
-            0xD8 => self.cld(),
-            0x58 => self.cli(),
-            0xB8 => self.clv(),
-
-            0x38 => self.sec(),
-            0xF8 => self.sed(),
-            0x78 => self.sei(),
-
-            0xCA => self.dex(),
-            0x88 => self.dey(),
-            0xE8 => self.inx(),
-            0xC8 => self.iny(),
-
-            0x48 => self.pha(),
-            0x08 => self.php(),
-            0x68 => self.pla(),
-            0x28 => self.plp(),
-
-            0x00 => self.brk(),
-            0x40 => self.rti(),
-
-            0x24 => self.bit_zeropage(),
-            0x2C => self.bit_absolute(),
-
-            0x0A => self.asl_accumulator(),
-            0x06 => self.asl_zeropage(),
-            0x16 => self.asl_zeropage_x(),
-            0x0E => self.asl_absolute(),
-            0x1E => self.asl_absolute_x(),
-
-            0x4A => self.lsr_accumulator(),
-            0x46 => self.lsr_zeropage(),
-            0x56 => self.lsr_zeropage_x(),
-            0x4E => self.lsr_absolute(),
-            0x5E => self.lsr_absolute_x(),
-
-            0x2A => self.rol_accumulator(),
-            0x26 => self.rol_zeropage(),
-            0x36 => self.rol_zeropage_x(),
-            0x2E => self.rol_absolute(),
-            0x3E => self.rol_absolute_x(),
-
-            0x6A => self.ror_accumulator(),
-            0x66 => self.ror_zeropage(),
-            0x76 => self.ror_zeropage_x(),
-            0x6E => self.ror_absolute(),
-            0x7E => self.ror_absolute_x(),
-
-            0xE6 => self.inc_zeropage(),
-            0xF6 => self.inc_zeropage_x(),
-            0xEE => self.inc_absolute(),
-            0xFE => self.inc_absolute_x(),
-
-            0xC6 => self.dec_zeropage(),
-            0xD6 => self.dec_zeropage_x(),
-            0xCE => self.dec_absolute(),
-            0xDE => self.dec_absolute_x(),
-
-            x => {
-                unreachable!("invalid instruction: {:X}", x);
+        // no instructions are fetched after STP until a reset and after WAI until an interrupt,
+        // which only `tick` takes
+        if self.stopped || self.waiting_for_interrupt {
+            self.clock_cycle();
+            return Ok(());
+        }
+
+        let pc = self.registers.pc;
+        self.instruction_pc = pc;
+        if self.resumed_at.take() != Some(pc) {
+            if let Some(breakpoint) = self.breakpoints.check_execute(pc) {
+                self.break_on(breakpoint, pc, None);
+                return Ok(());
+            }
+        }
+
+        if self
+            .tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.should_trace(pc))
+        {
+            let line = self.trace_line();
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&line);
             }
         }
+
+        let start_cycle = self.cycle;
+        let opcodes = self.opcodes();
+        self.decoded = self
+            .block_cache
+            .as_mut()
+            .and_then(|cache| cache.instruction(&self.bus, opcodes, pc));
+        let instruction = self.next();
+
+        let interrupt_bit = self.registers.get_interrupt_bit();
+
+        self.execute(instruction);
+        self.decoded = None;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_instruction(pc, self.cycle - start_cycle);
+        }
+
+        self.data_flags = CDL_DATA;
+        self.code_flags = if std::mem::take(&mut self.jumped_indirectly) {
+            CDL_CODE | CDL_INDIRECT_CODE
+        } else {
+            CDL_CODE
+        };
+
+        // CLI, SEI and PLP change the interrupt bit after the interrupt lines have been polled,
+        // so an IRQ is only taken (or inhibited) after the following instruction
+        self.irq_inhibited = match self.opcodes()[instruction as usize].operation {
+            Operation::Cli | Operation::Sei | Operation::Plp => interrupt_bit,
+            _ => self.registers.get_interrupt_bit(),
+        };
+
+        if let Some(hit) = self.break_hit.as_mut() {
+            hit.update_registers(&self.registers);
+        }
+
+        match self.halted {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn opcodes(&self) -> &'static [Opcode; 256] {
+        self.options.variant.opcodes()
+    }
+
+    /// Runs the operation of the opcode on the operand its addressing mode resolves to
+    fn execute(&mut self, instruction: u8) {
+        use AddressingMode::{Absolute, Immediate, Implied, Indirect};
+        use Operation::*;
+
+        let Opcode {
+            operation, mode, ..
+        } = self.opcodes()[instruction as usize];
+        match operation {
+            Adc => self.read_operand(mode, Self::adc),
+            And => self.read_operand(mode, Self::and),
+            Bit if mode == Immediate => self.read_operand(mode, Self::bit_immediate),
+            Bit => self.read_operand(mode, Self::bit),
+            Cmp => self.read_operand(mode, Self::cmp),
+            Cpx => self.read_operand(mode, Self::cpx),
+            Cpy => self.read_operand(mode, Self::cpy),
+            Eor => self.read_operand(mode, Self::eor),
+            Lda => self.read_operand(mode, |cpu, data| cpu.registers.update_a(data)),
+            Ldx => self.read_operand(mode, |cpu, data| cpu.registers.update_x(data)),
+            Ldy => self.read_operand(mode, |cpu, data| cpu.registers.update_y(data)),
+            Ora => self.read_operand(mode, Self::ora),
+            Sbc => self.read_operand(mode, Self::sbc),
+            Nop if mode == Implied => self.nop(),
+            Nop => self.read_operand(mode, |_, _| {}),
+
+            Sta => self.write_operand(mode, self.registers.a),
+            Stx => self.write_operand(mode, self.registers.x),
+            Sty => self.write_operand(mode, self.registers.y),
+
+            Asl => self.modify_operand(mode, self.shift_access(), Self::asl),
+            Lsr => self.modify_operand(mode, self.shift_access(), Self::lsr),
+            Rol => self.modify_operand(mode, self.shift_access(), Self::rol),
+            Ror => self.modify_operand(mode, self.shift_access(), Self::ror),
+            Inc => self.modify_operand(mode, Access::ReadModify, Self::inc),
+            Dec => self.modify_operand(mode, Access::ReadModify, Self::dec),
+
+            Jmp if mode == Absolute => self.jmp_absolute(),
+            Jmp if mode == Indirect => self.jmp_indirect(),
+            Jmp => self.jmp_absolute_x_indirect(),
+            Jsr => self.jsr(),
+            Rts => self.rts(),
+            Brk => self.brk(),
+            Rti => self.rti(),
+
+            Bcc => self.bcc(),
+            Bcs => self.bcs(),
+            Beq => self.beq(),
+            Bmi => self.bmi(),
+            Bne => self.bne(),
+            Bpl => self.bpl(),
+            Bvc => self.bvc(),
+            Bvs => self.bvs(),
+
+            Clc => self.clc(),
+            Cld => self.cld(),
+            Cli => self.cli(),
+            Clv => self.clv(),
+            Sec => self.sec(),
+            Sed => self.sed(),
+            Sei => self.sei(),
+
+            Tax => self.tax(),
+            Tay => self.tay(),
+            Tsx => self.tsx(),
+            Txa => self.txa(),
+            Txs => self.txs(),
+            Tya => self.tya(),
+            Dex => self.dex(),
+            Dey => self.dey(),
+            Inx => self.inx(),
+            Iny => self.iny(),
+
+            Pha => self.pha(),
+            Php => self.php(),
+            Pla => self.pla(),
+            Plp => self.plp(),
+
+            // unofficial opcodes
+            Lax if mode != Immediate => self.read_operand(mode, Self::lax),
+            Las => self.read_operand(mode, Self::las),
+            Anc => self.read_operand(mode, Self::anc),
+            Alr => self.read_operand(mode, Self::alr),
+            Arr => self.read_operand(mode, Self::arr),
+            Axs => self.read_operand(mode, Self::axs),
+            Sax => self.write_operand(mode, self.registers.a & self.registers.x),
+            Slo => self.modify_operand(mode, Access::ReadModify, Self::slo),
+            Rla => self.modify_operand(mode, Access::ReadModify, Self::rla),
+            Sre => self.modify_operand(mode, Access::ReadModify, Self::sre),
+            Rra => self.modify_operand(mode, Access::ReadModify, Self::rra),
+            Dcp => self.modify_operand(mode, Access::ReadModify, Self::dcp),
+            Isb => self.modify_operand(mode, Access::ReadModify, Self::isb),
+            Jam => self.halt(CpuError::Jammed {
+                opcode: instruction,
+                addr: self.instruction_pc,
+            }),
+            // XAA, SHA, TAS, SHY, SHX and LAX #imm depend on analog effects of the chip
+            Lax | Sha | Shx | Shy | Tas | Xaa => self.halt(CpuError::UnsupportedOpcode {
+                opcode: instruction,
+                addr: self.instruction_pc,
+            }),
+
+            // 65C02 instructions
+            Stz => self.write_operand(mode, 0),
+            Tsb => self.modify_operand(mode, Access::ReadModify, Self::tsb),
+            Trb => self.modify_operand(mode, Access::ReadModify, Self::trb),
+            Bra => self.bra(),
+            Phx => self.phx(),
+            Phy => self.phy(),
+            Plx => self.plx(),
+            Ply => self.ply(),
+            Rmb(bit) => self.rmb(bit),
+            Smb(bit) => self.smb(bit),
+            Bbr(bit) => self.bbr(bit),
+            Bbs(bit) => self.bbs(bit),
+            Wai => self.wai(),
+            Stp => self.stp(),
+            NopSingleCycle => {}
+            NopEightCycles => self.nop_absolute_8_cycles(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::cpu::bus::Bus;
-    use crate::cpu::Cpu;
+    use crate::cpu::opcodes::AddressingMode;
+    use crate::cpu::{
+        Cpu, CpuError, CpuOptions, CpuVariant, PendingInterrupts, STATUS_BREAK_BIT,
+        STATUS_BREAK_IGNORED_MASK, STATUS_CARRY_BIT, STATUS_DECIMAL_BIT, STATUS_IGNORED_BIT,
+        STATUS_INTERRUPT_BIT, STATUS_NEGATIVE_BIT, STATUS_OVERFLOW_BIT, STATUS_ZERO_BIT,
+    };
+    use crate::memory::test::DummyMemory;
+    use crate::memory::{Memory, Ram, RamFill};
     use crate::nes_rom::NesRom;
+    use crate::ppu::OAM_SIZE;
+    use std::cell::RefCell;
     use std::fs::File;
     use std::io::{BufRead, BufReader};
+    use std::rc::Rc;
+
+    /// The IRQ of the APU frame counter, which isn't emulated yet, so unlike the one of the
+    /// mapper the bus doesn't update it every cycle
+    const IRQ_SOURCE_FRAME_COUNTER: u8 = 1 << 1;
 
     #[test]
     fn nestest() {
         let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
         let memory_map = Bus::new(rom);
-        let mut cpu = Cpu::with_nes_options(memory_map, 1 << 16);
-        cpu.reset();
+        let mut cpu = Cpu::with_nes_options(memory_map);
+        cpu.power_on();
         cpu.registers.pc = 0xC000;
 
-
         let reference_log = File::open("./vendor/nestest/nestest.log").unwrap();
-        let mut idx = 1;
-        for line in BufReader::new(reference_log).lines().map(|l| l.unwrap()) {
-            if idx == 5004 {
-                // first inofficial opcode (not currently supported)
-                break;
-            }
-            let state = format!(
-                "{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
-                cpu.registers.pc,
-                cpu.registers.a,
-                cpu.registers.x,
-                cpu.registers.y,
-                cpu.registers.p,
-                cpu.registers.s
-            );
-            let expected = format!("{} {}", &line[0..4], &line[48..73]);
+        for (idx, line) in (1..).zip(BufReader::new(reference_log).lines().map(|l| l.unwrap())) {
+            let state = cpu.trace_line();
             println!("EMU: {}", state);
-            println!("REF: {}", expected);
+            println!("REF: {}", line);
             println!();
 
-            assert_eq!(state, expected, "mismatch on line {}", idx);
+            assert_eq!(state, line, "mismatch on line {}", idx);
+
+            cpu.step().unwrap();
+        }
+    }
+
+    /// Runs one of Klaus Dormann's functional tests until it traps, i.e. an instruction jumps or
+    /// branches to itself, and checks that it trapped at the success address
+    fn functional_test(path: &str, options: CpuOptions, success_addr: u16) {
+        let image = std::fs::read(path).unwrap_or_else(|e| {
+            panic!(
+                "{}: {}, check out the submodules with `git submodule update --init`",
+                path, e
+            )
+        });
+        let mut memory = Ram::new(0x10000);
+        for (addr, &byte) in image.iter().enumerate() {
+            memory.write(addr as u16, byte);
+        }
+
+        let mut cpu = Cpu::new(memory, options);
+        cpu.registers.pc = 0x0400;
+        loop {
+            let pc = cpu.registers.pc;
+            if let Err(e) = cpu.tick() {
+                panic!("{} after {} cycles\n{}", e, cpu.cycle, cpu.registers);
+            }
+            if cpu.registers.pc == pc {
+                break;
+            }
+        }
+
+        assert_eq!(
+            cpu.registers.pc, success_addr,
+            "trapped at {:04X} after {} cycles\n{}",
+            cpu.registers.pc, cpu.cycle, cpu.registers
+        );
+    }
+
+    #[test]
+    fn functional_test_6502() {
+        functional_test(
+            "./vendor/6502_65C02_functional_tests/bin_files/6502_functional_test.bin",
+            CpuOptions::default(),
+            0x3469,
+        );
+    }
+
+    #[test]
+    fn extended_opcodes_test_65c02() {
+        functional_test(
+            "./vendor/6502_65C02_functional_tests/bin_files/65C02_extended_opcodes_test.bin",
+            CpuOptions {
+                variant: CpuVariant::Wdc65C02,
+                ..Default::default()
+            },
+            0x24F1,
+        );
+    }
+
+    #[test]
+    fn irq() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let memory_map = Bus::new(rom);
+        let mut cpu = Cpu::with_nes_options(memory_map);
+        cpu.power_on();
+
+        // CLI, NOP, NOP (the IRQ handler of nestest is a single RTI)
+        for (i, opcode) in [0x58, 0xEA, 0xEA].into_iter().enumerate() {
+            cpu.bus.write(0x0200 + i as u16, opcode);
+        }
+        cpu.registers.pc = 0x0200;
+        cpu.bus.set_irq(IRQ_SOURCE_FRAME_COUNTER, true);
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0201);
+        // the cleared interrupt bit only takes effect after the next instruction
+        cpu.tick().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0202);
+        assert_eq!(cpu.registers.s, 0xFD);
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0202);
+        assert_eq!(cpu.bus.read(0x01FD), 0x02);
+        assert_eq!(cpu.bus.read(0x01FC), 0x02);
+        let pushed_status = cpu.bus.read(0x01FB);
+        assert_eq!(pushed_status & (1 << STATUS_BREAK_BIT), 0);
+        assert_ne!(pushed_status & (1 << STATUS_IGNORED_BIT), 0);
+        assert_eq!(pushed_status & (1 << STATUS_INTERRUPT_BIT), 0);
+
+        cpu.bus.set_irq(IRQ_SOURCE_FRAME_COUNTER, false);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.registers.pc, 0x0203);
+    }
+
+    #[test]
+    fn state_api() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+        assert_eq!(cpu.cycle(), 7);
+        assert_eq!(cpu.pending_interrupts(), PendingInterrupts::default());
+
+        // INX, PHP
+        cpu.bus.write(0x0200, 0xE8);
+        cpu.bus.write(0x0201, 0x08);
+        let registers = cpu.registers_mut();
+        registers.pc = 0x0200;
+        registers.x = 0x7F;
+        registers.s = 0xF0;
+        registers.update_carry_bit(true);
+        registers.update_decimal_bit(true);
+        let snapshot = cpu.registers().clone();
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let registers = cpu.registers().clone();
+        assert_eq!(registers.pc, 0x0202);
+        assert_eq!(registers.x, 0x80);
+        assert_eq!(registers.s, 0xEF);
+        assert!(registers.get_negative_bit());
+        assert!(!registers.get_zero_bit());
+        assert!(registers.get_carry_bit() && registers.get_decimal_bit());
+        assert_eq!(
+            cpu.bus.read(0x01F0),
+            registers.p | STATUS_BREAK_IGNORED_MASK
+        );
+        assert_eq!(cpu.cycle(), 7 + 2 + 3);
+        assert_ne!(registers, snapshot);
+
+        cpu.bus.set_irq(IRQ_SOURCE_FRAME_COUNTER, true);
+        assert!(cpu.pending_interrupts().irq);
+        assert!(cpu.registers().get_interrupt_bit());
+    }
+
+    #[test]
+    fn reset_and_power_on() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut bus = Bus::new(rom);
+        bus.ram_fill = RamFill::Ones;
+        let mut cpu = Cpu::with_nes_options(bus);
+        cpu.power_on();
+        assert_eq!(cpu.registers.s, 0xFD);
+        assert_eq!(cpu.registers.p, 0x24);
+        assert_eq!(cpu.bus.read(0x0000), 0xFF);
+
+        cpu.bus.write(0x0000, 0x12);
+        cpu.registers.a = 0x01;
+        cpu.registers.x = 0x02;
+        cpu.registers.y = 0x03;
+        cpu.registers.p = 0xC3;
+        cpu.reset();
+        let registers = cpu.registers();
+        assert_eq!((registers.a, registers.x, registers.y), (0x01, 0x02, 0x03));
+        assert_eq!(registers.s, 0xFA);
+        assert_eq!(registers.p, 0xC7);
+        assert_eq!(registers.pc, cpu.bus.reset_vector());
+        assert_eq!(cpu.bus.read(0x0000), 0x12);
+
+        cpu.power_on();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert_eq!(cpu.registers.s, 0xFD);
+        assert_eq!(cpu.bus.read(0x0000), 0xFF);
+        assert_eq!(cpu.cycle(), 7);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let memory_map = Bus::new(rom);
+        let mut cpu = Cpu::with_nes_options(memory_map);
+        cpu.power_on();
+
+        for i in 0..OAM_SIZE {
+            cpu.bus.write(0x0300 + i as u16, i as u8);
+        }
+        // LDA #$03, STA $4014
+        for (i, opcode) in [0xA9, 0x03, 0x8D, 0x14, 0x40].into_iter().enumerate() {
+            cpu.bus.write(0x0200 + i as u16, opcode);
+        }
+        cpu.registers.pc = 0x0200;
+
+        cpu.step().unwrap();
+        let start_cycle = cpu.cycle;
+        cpu.step().unwrap();
+        let dma_cycles = cpu.cycle - start_cycle - 4;
+        assert!(dma_cycles == 513 || dma_cycles == 514);
+
+        for i in 0..OAM_SIZE {
+            assert_eq!(cpu.bus.ppu.oam[i], i as u8);
+        }
+    }
+
+    #[test]
+    fn flat_memory() {
+        let mut memory = Ram::new(0x10000);
+        // LDX #$05, loop: DEX, BNE loop, STX $10
+        for (i, opcode) in [0xA2, 0x05, 0xCA, 0xD0, 0xFD, 0x86, 0x10]
+            .into_iter()
+            .enumerate()
+        {
+            memory.write(0x0400 + i as u16, opcode);
+        }
+        memory.write(0x10, 0xFF);
+        memory.write(0xFFFC, 0x00);
+        memory.write(0xFFFD, 0x04);
+
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.power_on();
+        assert_eq!(cpu.registers.pc, 0x0400);
+        for _ in 0..12 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 0x0407);
+        assert_eq!(Memory::read(&cpu.bus, 0x10), 0x00);
+    }
+
+    #[test]
+    fn nmos_decimal_mode() {
+        // runs ADC #m or SBC #m in decimal or binary mode and returns A and P
+        fn run(
+            cpu: &mut Cpu<Ram>,
+            opcode: u8,
+            a: u8,
+            m: u8,
+            carry: bool,
+            decimal: bool,
+        ) -> (u8, u8) {
+            cpu.bus.write(0x0200, opcode);
+            cpu.bus.write(0x0201, m);
+            cpu.registers.pc = 0x0200;
+            cpu.registers.a = a;
+            cpu.registers.p = 0;
+            cpu.registers.update_carry_bit(carry);
+            cpu.registers.update_decimal_bit(decimal);
+            cpu.step().unwrap();
+            (cpu.registers.a, cpu.registers.p)
+        }
+        fn from_bcd(value: u8) -> i16 {
+            (value >> 4) as i16 * 10 + (value & 0x0F) as i16
+        }
+        fn to_bcd(value: i16) -> u8 {
+            let value = value.rem_euclid(100) as u8;
+            ((value / 10) << 4) | (value % 10)
+        }
+        const C: u8 = 1 << STATUS_CARRY_BIT;
+        const Z: u8 = 1 << STATUS_ZERO_BIT;
+        const V: u8 = 1 << STATUS_OVERFLOW_BIT;
+        const N: u8 = 1 << STATUS_NEGATIVE_BIT;
+
+        let mut cpu = Cpu::new(Ram::new(0x10000), CpuOptions::default());
+
+        // examples from http://www.6502.org/tutorials/decimal_mode.html
+        assert_eq!(run(&mut cpu, 0x69, 0x99, 0x01, false, true).0, 0x00);
+        assert_eq!(cpu.registers.p & (C | Z | N), C | N);
+        assert_eq!(run(&mut cpu, 0x69, 0x79, 0x00, true, true).0, 0x80);
+        assert_eq!(cpu.registers.p & (C | V | N), V | N);
+        assert_eq!(run(&mut cpu, 0xE9, 0x00, 0x01, true, true).0, 0x99);
+        assert_eq!(cpu.registers.p & (C | Z | N), N);
+
+        // exhaustive test of all operands including invalid BCD, in the spirit of Bruce Clark's decimal test
+        for a in 0..=0xFFu8 {
+            for m in 0..=0xFFu8 {
+                for carry in [false, true] {
+                    let c = carry as i16;
+                    let valid = [a >> 4, a & 0x0F, m >> 4, m & 0x0F]
+                        .iter()
+                        .all(|digit| *digit < 10);
+
+                    let (result, p) = run(&mut cpu, 0x69, a, m, carry, true);
+                    let (_, binary_p) = run(&mut cpu, 0x69, a, m, carry, false);
+                    let mut lo = (a & 0x0F) as i16 + (m & 0x0F) as i16 + c;
+                    if lo > 9 {
+                        lo = ((lo + 6) & 0x0F) + 0x10;
+                    }
+                    let signed = (a & 0xF0) as i8 as i16 + (m & 0xF0) as i8 as i16 + lo;
+                    let expected_n = if signed & 0x80 != 0 { N } else { 0 };
+                    let expected_v = if (-128..=127).contains(&signed) { 0 } else { V };
+                    assert_eq!(
+                        p & (N | V | Z),
+                        expected_n | expected_v | (binary_p & Z),
+                        "ADC ${a:02X} + ${m:02X} + {c}"
+                    );
+                    if valid {
+                        let sum = from_bcd(a) + from_bcd(m) + c;
+                        assert_eq!(result, to_bcd(sum), "ADC ${a:02X} + ${m:02X} + {c}");
+                        assert_eq!(p & C != 0, sum > 99, "ADC ${a:02X} + ${m:02X} + {c}");
+                    }
+
+                    let (result, p) = run(&mut cpu, 0xE9, a, m, carry, true);
+                    let (_, binary_p) = run(&mut cpu, 0xE9, a, m, carry, false);
+                    assert_eq!(
+                        p,
+                        binary_p | 1 << STATUS_DECIMAL_BIT,
+                        "SBC ${a:02X} - ${m:02X} - {}",
+                        1 - c
+                    );
+                    if valid {
+                        let diff = from_bcd(a) - from_bcd(m) - (1 - c);
+                        assert_eq!(result, to_bcd(diff), "SBC ${a:02X} - ${m:02X} - {}", 1 - c);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nes_ignores_decimal_mode() {
+        let mut memory = Ram::new(0x10000);
+        // SED, SEC, LDA #$10, SBC #$01, CLC, ADC #$09
+        for (i, opcode) in [0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01, 0x18, 0x69, 0x09]
+            .into_iter()
+            .enumerate()
+        {
+            memory.write(0x0400 + i as u16, opcode);
+        }
 
-            idx += 1;
-            cpu.step();
+        let mut cpu = Cpu::new(
+            memory,
+            CpuOptions {
+                ignore_decimal_bit: true,
+                ..CpuOptions::default()
+            },
+        );
+        cpu.registers.pc = 0x0400;
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.a, 0x0F);
+        for _ in 0..2 {
+            cpu.step().unwrap();
         }
+        assert_eq!(cpu.registers.a, 0x18);
+    }
+
+    #[test]
+    fn jam() {
+        let mut memory = Ram::new(0x10000);
+        // NOP, JAM
+        memory.write(0x0400, 0xEA);
+        memory.write(0x0401, 0x02);
+        memory.write(0xFFFC, 0x00);
+        memory.write(0xFFFD, 0x04);
+
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.power_on();
+        cpu.tick().unwrap();
+        let error = CpuError::Jammed {
+            opcode: 0x02,
+            addr: 0x0401,
+        };
+        assert_eq!(cpu.tick(), Err(error));
+        assert_eq!(cpu.registers.pc, 0x0401);
+        let cycle = cpu.cycle;
+        assert_eq!(cpu.tick(), Err(error));
+        assert_eq!(cpu.cycle, cycle);
+
+        cpu.reset();
+        assert_eq!(cpu.halted(), None);
+        cpu.tick().unwrap();
+    }
+
+    #[test]
+    fn opcode_table_cycles() {
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+            for opcode in 0..=0xFF {
+                let options = CpuOptions {
+                    variant,
+                    ..Default::default()
+                };
+                // all operands and pointers are zero, so no page is crossed
+                let mut cpu = Cpu::new(Ram::new(0x10000), options);
+                cpu.bus.write(0x0200, opcode);
+                cpu.registers.pc = 0x0200;
+                cpu.registers.s = 0xFD;
+                let entry = cpu.opcodes()[opcode as usize];
+                if matches!(
+                    entry.mode,
+                    AddressingMode::Relative | AddressingMode::ZeropageRelative
+                ) || cpu.step().is_err()
+                {
+                    continue;
+                }
+                assert_eq!(
+                    cpu.cycle,
+                    u64::from(entry.cycles),
+                    "{:?} ${:02X} {}",
+                    variant,
+                    opcode,
+                    entry.mnemonic()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn write_only_ppu_registers() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+
+        // LDA #$1F, STA $2005, LDX $2000, STA $2002
+        for (i, opcode) in [
+            0xA9, 0x1F, 0x8D, 0x05, 0x20, 0xAE, 0x00, 0x20, 0x8D, 0x02, 0x20,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            cpu.bus.write(0x0200 + i as u16, opcode);
+        }
+        cpu.registers.pc = 0x0200;
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.x, 0x1F);
+    }
+
+    #[test]
+    fn dummy_memory() {
+        // reads return the low byte of the address, so $0085 decodes as STA $86
+        let memory = Rc::new(RefCell::new(DummyMemory::new()));
+        let mut cpu = Cpu::new(memory.clone(), CpuOptions::default());
+        cpu.registers.pc = 0x0085;
+        cpu.registers.a = 0x42;
+        cpu.step().unwrap();
+
+        assert_eq!(memory.borrow().last_write_addr(), 0x0086);
+        assert_eq!(memory.borrow().last_write_value(), 0x42);
+        assert_eq!(cpu.cycle, 3);
     }
 }
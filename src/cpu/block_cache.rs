@@ -0,0 +1,355 @@
+use crate::cpu::opcodes::{Opcode, Operation};
+use crate::cpu::CpuBus;
+use std::collections::HashMap;
+
+/// Blocks without a jump are split after this many instructions
+const MAX_BLOCK_LEN: usize = 64;
+
+/// What is mapped at an address, as far as the block cache is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CodeBank {
+    /// ROM that never changes while it is mapped, identified by the bank
+    Rom(u32),
+    /// Memory that can be written, its code is decoded again once it was modified
+    Ram,
+}
+
+/// An instruction together with its operand bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct DecodedInstruction {
+    pub(super) addr: u16,
+    pub(super) len: u16,
+    pub(super) bytes: [u8; 3],
+}
+
+struct Block {
+    instructions: Vec<DecodedInstruction>,
+    /// The generation of the cache when a block in RAM was decoded, it is decoded again in later
+    /// ones
+    generation: u64,
+}
+
+impl Block {
+    /// Decodes instructions up to the next change of the control flow, `None` if the first
+    /// instruction is not completely in `bank`
+    fn decode(bus: &impl CpuBus, opcodes: &[Opcode; 256], bank: CodeBank, pc: u16) -> Option<Self> {
+        let mut instructions = Vec::new();
+        let mut addr = pc;
+        while instructions.len() < MAX_BLOCK_LEN {
+            let opcode = &opcodes[bus.peek(addr) as usize];
+            let len = opcode.len();
+            if (0..len).any(|i| bus.code_bank(addr.wrapping_add(i)) != Some(bank)) {
+                break;
+            }
+            let mut bytes = [0; 3];
+            for (i, byte) in bytes.iter_mut().take(len as usize).enumerate() {
+                *byte = bus.peek(addr.wrapping_add(i as u16));
+            }
+            instructions.push(DecodedInstruction { addr, len, bytes });
+            addr = addr.wrapping_add(len);
+            if ends_block(opcode.operation) {
+                break;
+            }
+        }
+        (!instructions.is_empty()).then_some(Self {
+            instructions,
+            generation: 0,
+        })
+    }
+}
+
+fn ends_block(operation: Operation) -> bool {
+    use Operation::*;
+    matches!(
+        operation,
+        Bcc | Bcs
+            | Beq
+            | Bmi
+            | Bne
+            | Bpl
+            | Bvc
+            | Bvs
+            | Bra
+            | Bbr(_)
+            | Bbs(_)
+            | Jmp
+            | Jsr
+            | Rts
+            | Rti
+            | Brk
+            | Jam
+            | Wai
+            | Stp
+    )
+}
+
+struct Cursor {
+    block: usize,
+    /// The instruction that follows if execution continues in the block
+    index: usize,
+}
+
+/// Instructions decoded into basic blocks, so executing them doesn't read the opcode and operands
+/// from the bus again
+///
+/// Blocks are keyed by the bank mapped at their start and the PC, blocks of banks that are
+/// switched out are reused when the bank is mapped again. The block entered at each address is
+/// remembered, so the bank only has to be looked up again after a write that can switch banks
+/// (`CpuBus::switches_banks`) or that modifies decoded code in RAM. Such a write starts a new
+/// generation of the cache, which forgets the entered blocks and decodes the blocks in RAM again.
+pub(super) struct BlockCache {
+    blocks: Vec<Block>,
+    keys: HashMap<(CodeBank, u16), usize>,
+    /// The generation and the block entered at every address
+    entries: Vec<(u64, usize)>,
+    /// The generation in which RAM at every address was decoded into a block
+    ram_code: Vec<u64>,
+    generation: u64,
+    cursor: Option<Cursor>,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self {
+            blocks: Vec::new(),
+            keys: HashMap::new(),
+            entries: vec![(0, 0); 0x10000],
+            ram_code: vec![0; 0x10000],
+            generation: 1,
+            cursor: None,
+        }
+    }
+}
+
+impl BlockCache {
+    /// The instruction at `pc`, `None` if memory at `pc` is not cacheable
+    pub(super) fn instruction(
+        &mut self,
+        bus: &impl CpuBus,
+        opcodes: &[Opcode; 256],
+        pc: u16,
+    ) -> Option<DecodedInstruction> {
+        if let Some(cursor) = self.cursor.as_mut() {
+            let next = self.blocks[cursor.block].instructions.get(cursor.index);
+            if let Some(instruction) = next.filter(|instruction| instruction.addr == pc) {
+                cursor.index += 1;
+                return Some(*instruction);
+            }
+        }
+
+        self.cursor = None;
+        let block = match self.entries[pc as usize] {
+            (generation, block) if generation == self.generation => block,
+            _ => {
+                let block = self.lookup(bus, opcodes, pc)?;
+                self.entries[pc as usize] = (self.generation, block);
+                block
+            }
+        };
+        self.cursor = Some(Cursor { block, index: 1 });
+        Some(self.blocks[block].instructions[0])
+    }
+
+    fn lookup(&mut self, bus: &impl CpuBus, opcodes: &[Opcode; 256], pc: u16) -> Option<usize> {
+        let bank = bus.code_bank(pc)?;
+        let block = match self.keys.get(&(bank, pc)) {
+            Some(&block) if bank != CodeBank::Ram => return Some(block),
+            Some(&block) if self.blocks[block].generation == self.generation => return Some(block),
+            Some(&block) => {
+                self.blocks[block] = Block::decode(bus, opcodes, bank, pc)?;
+                block
+            }
+            None => {
+                self.blocks.push(Block::decode(bus, opcodes, bank, pc)?);
+                self.keys.insert((bank, pc), self.blocks.len() - 1);
+                self.blocks.len() - 1
+            }
+        };
+        if bank == CodeBank::Ram {
+            let block = &mut self.blocks[block];
+            block.generation = self.generation;
+            for instruction in &block.instructions {
+                for i in 0..instruction.len {
+                    self.ram_code[instruction.addr.wrapping_add(i) as usize] = self.generation;
+                }
+            }
+        }
+        Some(block)
+    }
+
+    /// Called for every write of the CPU
+    pub(super) fn write(&mut self, bus: &impl CpuBus, addr: u16) {
+        if self.ram_code[addr as usize] == self.generation || bus.switches_banks(addr) {
+            self.invalidate();
+        }
+    }
+
+    /// Starts a new generation, continuing with a lookup at the next instruction
+    pub(super) fn invalidate(&mut self) {
+        self.generation += 1;
+        self.cursor = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::block_cache::CodeBank;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::{Cpu, CpuBus, CpuOptions};
+    use crate::memory::{Memory, Ram};
+    use crate::nes_rom::NesRom;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+
+    /// Steps a CPU with and without the block cache and compares their state after every
+    /// instruction
+    fn assert_same_execution<B: CpuBus>(new_cpu: impl Fn() -> Cpu<B>, steps: usize) -> Cpu<B> {
+        let mut interpreted = new_cpu();
+        let mut cached = new_cpu();
+        cached.set_block_cache(true);
+        for step in 0..steps {
+            interpreted.step().unwrap();
+            cached.step().unwrap();
+            assert_eq!(cached.registers, interpreted.registers, "step {}", step);
+            assert_eq!(cached.cycle, interpreted.cycle, "step {}", step);
+        }
+        cached
+    }
+
+    #[test]
+    fn nestest_with_block_cache() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+        cpu.set_block_cache(true);
+        cpu.registers.pc = 0xC000;
+
+        let reference_log = File::open("./vendor/nestest/nestest.log").unwrap();
+        for (idx, line) in (1..).zip(BufReader::new(reference_log).lines().map(|l| l.unwrap())) {
+            assert_eq!(cpu.trace_line(), line, "mismatch on line {}", idx);
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // loop: LDA #$00, INC $0201, INX, STA $0300,X, CPX #$08, BNE loop
+        let program = [
+            0xA9, 0x00, 0xEE, 0x01, 0x02, 0xE8, 0x9D, 0x00, 0x03, 0xE0, 0x08, 0xD0, 0xF3,
+        ];
+        let cpu = assert_same_execution(
+            || {
+                let mut memory = Ram::new(0x10000);
+                for (i, &byte) in program.iter().enumerate() {
+                    Memory::write(&mut memory, 0x0200 + i as u16, byte);
+                }
+                let mut cpu = Cpu::new(memory, CpuOptions::default());
+                cpu.registers.pc = 0x0200;
+                cpu
+            },
+            8 * 6,
+        );
+        for i in 1..=8 {
+            assert_eq!(Memory::read(&cpu.bus, 0x0300 + i), i as u8 - 1);
+        }
+    }
+
+    #[test]
+    fn code_modified_by_another_block() {
+        // loop: JSR $0300, INC $0301, JMP loop and LDA #$00, RTS at $0300
+        let program = [0x20, 0x00, 0x03, 0xEE, 0x01, 0x03, 0x4C, 0x00, 0x02];
+        let cpu = assert_same_execution(
+            || {
+                let mut memory = Ram::new(0x10000);
+                for (i, &byte) in program.iter().enumerate() {
+                    Memory::write(&mut memory, 0x0200 + i as u16, byte);
+                }
+                for (i, byte) in [0xA9, 0x00, 0x60].into_iter().enumerate() {
+                    Memory::write(&mut memory, 0x0300 + i as u16, byte);
+                }
+                let mut cpu = Cpu::new(memory, CpuOptions::default());
+                cpu.registers.pc = 0x0200;
+                cpu
+            },
+            4 * 5,
+        );
+        assert_eq!(cpu.registers.a, 3);
+    }
+
+    /// 32 KiB of RAM followed by one of two 32 KiB ROM banks, selected by writing to ROM
+    struct BankedMemory {
+        ram: Ram,
+        banks: [Vec<u8>; 2],
+        bank: usize,
+    }
+
+    impl CpuBus for BankedMemory {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.peek(addr)
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            match addr {
+                0x8000.. => self.bank = data as usize & 1,
+                _ => Memory::write(&mut self.ram, addr, data),
+            }
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            match addr {
+                0x8000.. => self.banks[self.bank][addr as usize - 0x8000],
+                _ => Memory::read(&self.ram, addr),
+            }
+        }
+
+        fn code_bank(&self, addr: u16) -> Option<CodeBank> {
+            match addr {
+                0x8000.. => Some(CodeBank::Rom(self.bank as u32)),
+                _ => Some(CodeBank::Ram),
+            }
+        }
+    }
+
+    #[test]
+    fn bank_switching() {
+        // LDA #(1 - bank), STA $8000, LDX #($10 + bank), INY, JMP $8000 in both banks, the
+        // instructions after the store come from the other bank
+        let banks = [0, 1].map(|bank: u8| {
+            let mut data = vec![0xEA; 0x8000];
+            let program = [
+                0xA9,
+                1 - bank,
+                0x8D,
+                0x00,
+                0x80,
+                0xA2,
+                0x10 + bank,
+                0xC8,
+                0x4C,
+                0x00,
+                0x80,
+            ];
+            data[..program.len()].copy_from_slice(&program);
+            data
+        });
+        let mut cpu = assert_same_execution(
+            || {
+                let memory = BankedMemory {
+                    ram: Ram::new(0x8000),
+                    banks: banks.clone(),
+                    bank: 0,
+                };
+                let mut cpu = Cpu::new(memory, CpuOptions::default());
+                cpu.registers.pc = 0x8000;
+                cpu
+            },
+            5 * 3,
+        );
+        assert_eq!(cpu.registers.x, 0x11);
+        assert_eq!(cpu.registers.y, 3);
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.x, 0x10);
+    }
+}
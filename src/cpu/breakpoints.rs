@@ -0,0 +1,238 @@
+use crate::cpu::Registers;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Pauses before the instruction at the address is executed
+    Execute(u16),
+    Read(u16),
+    Write(u16),
+    /// Reads of a PPU register (0-7) through any of its mirrors
+    PpuRegisterRead(u8),
+    /// Writes to a PPU register (0-7) through any of its mirrors
+    PpuRegisterWrite(u8),
+}
+
+fn ppu_register(addr: u16) -> Option<u8> {
+    (0x2000..0x4000)
+        .contains(&addr)
+        .then_some((addr & 0x7) as u8)
+}
+
+impl Breakpoint {
+    fn matches_read(&self, addr: u16) -> bool {
+        match *self {
+            Breakpoint::Read(a) => a == addr,
+            Breakpoint::PpuRegisterRead(register) => ppu_register(addr) == Some(register),
+            _ => false,
+        }
+    }
+
+    fn matches_write(&self, addr: u16) -> bool {
+        match *self {
+            Breakpoint::Write(a) => a == addr,
+            Breakpoint::PpuRegisterWrite(register) => ppu_register(addr) == Some(register),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Execute(addr) => write!(f, "breakpoint at ${:04X}", addr),
+            Breakpoint::Read(addr) => write!(f, "read watchpoint at ${:04X}", addr),
+            Breakpoint::Write(addr) => write!(f, "write watchpoint at ${:04X}", addr),
+            Breakpoint::PpuRegisterRead(register) => {
+                write!(f, "read of PPU register ${:04X}", 0x2000 + *register as u16)
+            }
+            Breakpoint::PpuRegisterWrite(register) => {
+                write!(
+                    f,
+                    "write to PPU register ${:04X}",
+                    0x2000 + *register as u16
+                )
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Breakpoints {
+    /// Returns false if the breakpoint was already set
+    pub fn add(&mut self, breakpoint: Breakpoint) -> bool {
+        if self.breakpoints.contains(&breakpoint) {
+            return false;
+        }
+        self.breakpoints.push(breakpoint);
+        true
+    }
+
+    /// Returns false if the breakpoint was not set
+    pub fn remove(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| *b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    pub(super) fn check_execute(&self, pc: u16) -> Option<Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|b| **b == Breakpoint::Execute(pc))
+            .copied()
+    }
+
+    pub(super) fn check_read(&self, addr: u16) -> Option<Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|b| b.matches_read(addr))
+            .copied()
+    }
+
+    pub(super) fn check_write(&self, addr: u16) -> Option<Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|b| b.matches_write(addr))
+            .copied()
+    }
+}
+
+/// Why the CPU paused, watchpoints fire during an instruction which is completed before pausing
+pub struct BreakHit {
+    pub breakpoint: Breakpoint,
+    /// Address of the instruction that triggered the break
+    pub pc: u16,
+    pub addr: u16,
+    /// The value that was read or written, if any
+    pub value: Option<u8>,
+    registers: Registers,
+}
+
+impl BreakHit {
+    pub(super) fn new(
+        breakpoint: Breakpoint,
+        pc: u16,
+        addr: u16,
+        value: Option<u8>,
+        registers: &Registers,
+    ) -> Self {
+        Self {
+            breakpoint,
+            pc,
+            addr,
+            value,
+            registers: registers.clone(),
+        }
+    }
+
+    /// Updates the register snapshot once the instruction has completed
+    pub(super) fn update_registers(&mut self, registers: &Registers) {
+        self.registers = registers.clone();
+    }
+}
+
+impl fmt::Display for BreakHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hit {}", self.breakpoint)?;
+        if self.breakpoint != Breakpoint::Execute(self.pc) {
+            write!(f, " (${:04X}", self.addr)?;
+            if let Some(value) = self.value {
+                write!(f, " = ${:02X}", value)?;
+            }
+            write!(f, ") by instruction at ${:04X}", self.pc)?;
+        }
+        write!(f, "\n{}", self.registers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::breakpoints::Breakpoint;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::{Cpu, CpuOptions};
+    use crate::memory::{Memory, Ram};
+    use crate::nes_rom::NesRom;
+
+    #[test]
+    fn execute_and_watchpoints() {
+        let mut memory = Ram::new(0x10000);
+        // LDA $10, STA $11, NOP
+        for (i, opcode) in [0xA5, 0x10, 0x85, 0x11, 0xEA].into_iter().enumerate() {
+            memory.write(0x0400 + i as u16, opcode);
+        }
+        memory.write(0x10, 0x42);
+
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.registers.pc = 0x0400;
+        cpu.breakpoints_mut().add(Breakpoint::Execute(0x0402));
+        cpu.breakpoints_mut().add(Breakpoint::Read(0x0010));
+        cpu.breakpoints_mut().add(Breakpoint::Write(0x0011));
+
+        cpu.tick().unwrap();
+        let hit = cpu.break_hit().unwrap();
+        assert_eq!(hit.breakpoint, Breakpoint::Read(0x0010));
+        assert_eq!(hit.pc, 0x0400);
+        assert_eq!(hit.value, Some(0x42));
+        assert_eq!(cpu.registers.pc, 0x0402);
+
+        // paused, nothing happens until resumed
+        let cycle = cpu.cycle;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.cycle, cycle);
+
+        cpu.resume();
+        cpu.tick().unwrap();
+        assert_eq!(
+            cpu.break_hit().unwrap().breakpoint,
+            Breakpoint::Execute(0x0402)
+        );
+        assert_eq!(cpu.cycle, cycle);
+
+        cpu.resume();
+        cpu.tick().unwrap();
+        let hit = cpu.break_hit().unwrap();
+        assert_eq!(hit.breakpoint, Breakpoint::Write(0x0011));
+        assert_eq!(
+            hit.to_string().lines().next().unwrap(),
+            "Hit write watchpoint at $0011 ($0011 = $42) by instruction at $0402"
+        );
+
+        cpu.resume();
+        cpu.tick().unwrap();
+        assert!(cpu.break_hit().is_none());
+        assert_eq!(cpu.registers.pc, 0x0405);
+    }
+
+    #[test]
+    fn ppu_register_breakpoint() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+
+        // LDA #$3F, STA $2000, STA $3FFE
+        for (i, opcode) in [0xA9, 0x3F, 0x8D, 0x00, 0x20, 0x8D, 0xFE, 0x3F]
+            .into_iter()
+            .enumerate()
+        {
+            cpu.bus.write(0x0200 + i as u16, opcode);
+        }
+        cpu.registers.pc = 0x0200;
+        cpu.breakpoints_mut().add(Breakpoint::PpuRegisterWrite(0x6));
+
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        let hit = cpu.break_hit().unwrap();
+        assert_eq!(hit.breakpoint, Breakpoint::PpuRegisterWrite(0x6));
+        assert_eq!(hit.addr, 0x3FFE);
+        assert_eq!(hit.pc, 0x0205);
+    }
+}
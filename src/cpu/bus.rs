@@ -1,39 +1,68 @@
+use crate::cpu::block_cache::CodeBank;
+use crate::cpu::cdl::{CodeDataLogger, CDL_CHR_READ};
 use crate::cpu::controller::Controller;
 use crate::cpu::{INTERRUPT_VECTOR_RES_HI, INTERRUPT_VECTOR_RES_LO};
-use crate::memory::{Memory, Ram};
+use crate::mapper;
+use crate::memory::{Memory, Ram, RamFill};
 use crate::nes_rom::NesRom;
 use crate::ppu::ppu_memory::PpuMemory;
-use crate::ppu::{Ppu, OAM_SIZE};
+use crate::ppu::Ppu;
+
+pub const IRQ_SOURCE_MAPPER: u8 = 1 << 0;
 
 pub struct Bus {
     sram: Ram,
     pub rom: NesRom,
-    prg_ram: Ram,
     pub ppu: Ppu<PpuMemory>,
     pub controller: Controller,
-    pub cycle: u32,
+    irq_sources: u8,
+    oam_dma: Option<u8>,
+    /// The last value put on the PPU data bus, returned by reads of write-only registers
+    ppu_latch: u8,
+    /// Contents of RAM after the next power-on
+    pub ram_fill: RamFill,
+    pub cdl: Option<CodeDataLogger>,
 }
 
 impl Bus {
     pub fn new(rom: NesRom) -> Self {
+        Self::with_ram_fill(rom, RamFill::default())
+    }
+
+    fn with_ram_fill(rom: NesRom, ram_fill: RamFill) -> Self {
         Self {
-            sram: Ram::new(0x800),
-            rom: rom.clone(),
-            prg_ram: Ram::new(0x2000),
-            ppu: Ppu::new(rom.chr_rom, rom.nametable_mirroring),
+            sram: Ram::with_fill(0x800, ram_fill),
+            ppu: Ppu::new(mapper::new(&rom, ram_fill)),
+            rom,
             controller: Controller::new(),
-            cycle: 0,
+            irq_sources: 0,
+            oam_dma: None,
+            ppu_latch: 0,
+            ram_fill,
+            cdl: None,
         }
     }
 
-    pub fn tick(&mut self, cycle: u32) {
-        let delta = cycle - self.cycle;
-        self.cycle = cycle;
-        self.ppu.tick(delta * 3);
+    /// Restores the state after turning the console off and on, including the contents of RAM
+    pub fn power_on(&mut self) {
+        let cdl = self.cdl.take();
+        *self = Self::with_ram_fill(self.rom.clone(), self.ram_fill);
+        self.cdl = cdl;
     }
 
-    pub fn poll_nmi(&mut self) -> bool {
-        self.ppu.poll_nmi()
+    /// The reset button only reaches the CPU, PPU and APU, RAM and the cartridge keep their state
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        // TODO silence the APU once it is emulated
+        self.oam_dma = None;
+    }
+
+    pub fn set_irq(&mut self, source: u8, asserted: bool) {
+        if asserted {
+            self.irq_sources |= source;
+        } else {
+            self.irq_sources &= !source;
+        }
     }
 
     pub fn poll_new_frame(&mut self) -> bool {
@@ -45,24 +74,30 @@ impl Bus {
             self.sram.read(a & 0x07FF)
         } else if (0x2000..0x4000).contains(&a) {
             let register = (a - 0x2000) % 8;
-            match register {
+            self.ppu_latch = match register {
                 2 => self.ppu.read_ppu_status(),
                 4 => self.ppu.read_oam_data(),
-                7 => self.ppu.read_ppu_data(),
-                _ => panic!(
-                    "Unexpected PPU register read: {:#X} (Register {})",
-                    a, register
-                ),
-            }
+                7 => {
+                    let addr = self.ppu.vram_addr();
+                    let offset = match addr {
+                        0x0000..0x2000 => self.ppu.memory.mapper.chr_rom_offset(addr),
+                        _ => None,
+                    };
+                    if let (Some(cdl), Some(offset)) = (self.cdl.as_mut(), offset) {
+                        cdl.log_chr(offset..offset + 1, CDL_CHR_READ);
+                    }
+                    self.ppu.read_ppu_data()
+                }
+                _ => self.ppu_latch,
+            };
+            self.ppu_latch
         } else if a == 0x4016 {
             self.controller.read()
         } else if a == 0x4017 {
             // TODO player 2 controller
             0
-        } else if (0x6000..0x8000).contains(&a) {
-            self.prg_ram.read(a - 0x6000)
-        } else if a >= 0x8000 {
-            self.rom.prg_rom[(a as usize - 0x8000) % self.rom.prg_rom.len()]
+        } else if a >= 0x6000 {
+            self.ppu.memory.mapper.read_prg(a)
         } else {
             println!("Tried to read unmapped address: {:#X}", a);
             0
@@ -74,6 +109,7 @@ impl Bus {
             self.sram.write(a & 0x07FF, v);
         } else if (0x2000..0x4000).contains(&a) {
             let register = (a - 0x2000) % 8;
+            self.ppu_latch = v;
             match register {
                 0 => self.ppu.write_ppu_ctrl(v),
                 1 => self.ppu.write_ppu_mask(v),
@@ -82,35 +118,114 @@ impl Bus {
                 5 => self.ppu.write_ppu_scroll(v),
                 6 => self.ppu.write_ppu_addr(v),
                 7 => self.ppu.write_ppu_data(v),
-                _ => panic!(
-                    "Unexpected PPU register read: {:#X} (Register {})",
-                    a, register
-                ),
+                // PPUSTATUS is read-only
+                _ => {}
             };
         } else if a == 0x4014 {
-            for i in 0..OAM_SIZE {
-                let addr = ((v as u16) << 8) | (i as u16);
-                let value = self.read(addr);
-                self.ppu.write_oam_data(value);
-            }
+            // the transfer itself is driven by the CPU, which is halted while it runs
+            self.oam_dma = Some(v);
         } else if a == 0x4016 {
             self.controller.write(v);
         } else if (0x4000..=0x4017).contains(&a) {
             // TODO APU
-        } else if (0x6000..0x8000).contains(&a) {
-            self.prg_ram.write(a - 0x6000, v);
+        } else if a >= 0x6000 {
+            self.ppu.memory.mapper.write_prg(a, v);
         } else {
             println!("Tried to write to unmapped address: {:#X}", a)
         }
     }
 
+    /// Reads without side effects, registers read as $FF like in Nintendulator logs
+    pub fn peek(&self, a: u16) -> u8 {
+        if a < 0x2000 {
+            self.sram.read(a & 0x07FF)
+        } else if a >= 0x6000 {
+            self.ppu.memory.mapper.read_prg(a)
+        } else {
+            0xFF
+        }
+    }
+
     pub fn reset_vector(&self) -> u16 {
-        let hi = self.rom.prg_rom
-            [(INTERRUPT_VECTOR_RES_HI - 0x8000) as usize % self.rom.prg_rom.len()]
-            as u16;
-        let lo = self.rom.prg_rom
-            [(INTERRUPT_VECTOR_RES_LO - 0x8000) as usize % self.rom.prg_rom.len()]
-            as u16;
+        let mapper = &self.ppu.memory.mapper;
+        let hi = mapper.read_prg(INTERRUPT_VECTOR_RES_HI) as u16;
+        let lo = mapper.read_prg(INTERRUPT_VECTOR_RES_LO) as u16;
         (hi << 8) | lo
     }
 }
+
+// the trait is not imported, its methods would be ambiguous with `Memory` on the RAM fields
+impl crate::cpu::CpuBus for Bus {
+    /// Advances the rest of the system by one CPU cycle
+    fn tick(&mut self) {
+        self.ppu.memory.mapper.tick();
+        self.ppu.tick(3);
+        let irq = self.ppu.memory.mapper.irq();
+        self.set_irq(IRQ_SOURCE_MAPPER, irq);
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    fn power_on(&mut self) {
+        Bus::power_on(self)
+    }
+
+    fn reset(&mut self) {
+        Bus::reset(self)
+    }
+
+    fn nmi_pending(&self) -> bool {
+        self.ppu.nmi_pending()
+    }
+
+    /// The IRQ line is level triggered and stays asserted as long as any source holds it
+    fn poll_irq(&self) -> bool {
+        self.irq_sources != 0
+    }
+
+    fn poll_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+
+    fn log_code_data(&mut self, addr: u16, flags: u8) {
+        let Some(cdl) = self.cdl.as_mut() else {
+            return;
+        };
+        if let Some(offset) = self.ppu.memory.mapper.prg_rom_offset(addr) {
+            cdl.log_prg(offset, addr, flags);
+        }
+    }
+
+    /// PRG ROM is identified by the 8 KiB bank mapped at the address. Code in the internal RAM
+    /// isn't cached, writes to its mirrors wouldn't be noticed.
+    fn code_bank(&self, addr: u16) -> Option<CodeBank> {
+        match self.ppu.memory.mapper.prg_rom_offset(addr) {
+            Some(offset) => Some(CodeBank::Rom((offset & !0x1FFF) as u32)),
+            None if addr >= 0x6000 => Some(CodeBank::Ram),
+            None => None,
+        }
+    }
+
+    /// The registers of the mappers are all above the APU and I/O registers
+    fn switches_banks(&self, addr: u16) -> bool {
+        addr >= 0x4020
+    }
+
+    fn ppu_position(&self) -> Option<(u32, u32)> {
+        Some((self.ppu.scanline, self.ppu.cycle))
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        Bus::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        Bus::write(self, addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+/// The byte was executed as an opcode or operand
+pub const CDL_CODE: u8 = 1 << 0;
+/// The byte was read by an instruction
+pub const CDL_DATA: u8 = 1 << 1;
+/// The byte is the target of an indirect jump
+pub const CDL_INDIRECT_CODE: u8 = 1 << 4;
+/// The byte was read through a pointer with (zp,X) or (zp),Y addressing
+pub const CDL_INDIRECT_DATA: u8 = 1 << 5;
+
+/// The tile was drawn by the renderer
+pub const CDL_CHR_RENDERED: u8 = 1 << 0;
+/// The byte was read through PPUDATA
+pub const CDL_CHR_READ: u8 = 1 << 1;
+
+/// Records how every byte of PRG and CHR ROM was used, in the format of the FCEUX Code/Data
+/// Logger (https://fceux.com/web/help/CodeDataLogger.html)
+///
+/// A .cdl file contains one byte of flags per PRG ROM byte followed by one per CHR ROM byte.
+/// Bits 2-3 of the PRG flags hold the 8 KiB window of the CPU address space the byte was
+/// accessed through.
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        Self {
+            prg: vec![0; prg_rom_size],
+            chr: vec![0; chr_rom_size],
+        }
+    }
+
+    /// Continues a log, fails if the size of the file does not match the ROM
+    pub fn load(
+        mut reader: impl Read,
+        prg_rom_size: usize,
+        chr_rom_size: usize,
+    ) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() != prg_rom_size + chr_rom_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "CDL file has {} bytes, expected {} for the loaded ROM",
+                    data.len(),
+                    prg_rom_size + chr_rom_size
+                ),
+            ));
+        }
+        let chr = data.split_off(prg_rom_size);
+        Ok(Self { prg: data, chr })
+    }
+
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&self.prg)?;
+        writer.write_all(&self.chr)
+    }
+
+    /// `addr` is the CPU address the byte at `offset` was accessed through
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            *byte |= flags | (((addr >> 13) & 0x3) as u8) << 2;
+        }
+    }
+
+    pub fn log_chr(&mut self, range: Range<usize>, flags: u8) {
+        let end = range.end.min(self.chr.len());
+        for byte in self.chr[range.start.min(end)..end].iter_mut() {
+            *byte |= flags;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::bus::Bus;
+    use crate::cpu::cdl::{
+        CodeDataLogger, CDL_CODE, CDL_DATA, CDL_INDIRECT_CODE, CDL_INDIRECT_DATA,
+    };
+    use crate::cpu::Cpu;
+    use crate::nes_rom::NesRom;
+
+    #[test]
+    fn log_prg_accesses() {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let (prg_size, chr_size) = (rom.prg_rom.len(), rom.chr_rom.len());
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+        cpu.bus.cdl = Some(CodeDataLogger::new(prg_size, chr_size));
+
+        // LDA $C010, LDY #$01, LDA ($10),Y, JMP ($0012)
+        let program = [0xAD, 0x10, 0xC0, 0xA0, 0x01, 0xB1, 0x10, 0x6C, 0x12, 0x00];
+        for (i, byte) in program.into_iter().enumerate() {
+            cpu.bus.write(0x0200 + i as u16, byte);
+        }
+        // pointers to $C1FF and $C000
+        for (i, byte) in [0xFF, 0xC1, 0x00, 0xC0].into_iter().enumerate() {
+            cpu.bus.write(0x0010 + i as u16, byte);
+        }
+        cpu.registers.pc = 0x0200;
+        // the last instruction is JMP $C5F5 at $C000
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+
+        // $C000-$DFFF is the third 8 KiB window
+        const WINDOW: u8 = 2 << 2;
+        let cdl = cpu.bus.cdl.take().unwrap();
+        assert_eq!(cdl.prg[0x0010], CDL_DATA | WINDOW);
+        assert_eq!(cdl.prg[0x0200], CDL_DATA | CDL_INDIRECT_DATA | WINDOW);
+        // the dummy read from the wrong page is not logged
+        assert_eq!(cdl.prg[0x0100], 0);
+        for offset in 0x0000..0x0003 {
+            assert_eq!(cdl.prg[offset], CDL_CODE | CDL_INDIRECT_CODE | WINDOW);
+        }
+        assert_eq!(cdl.prg[0x0003], 0);
+        assert_eq!(cdl.prg[0x05F5], CDL_CODE | WINDOW);
+
+        let mut file = Vec::new();
+        cdl.save(&mut file).unwrap();
+        assert_eq!(file.len(), prg_size + chr_size);
+        let loaded = CodeDataLogger::load(file.as_slice(), prg_size, chr_size).unwrap();
+        assert_eq!(loaded.prg, cdl.prg);
+        assert!(CodeDataLogger::load(file.as_slice(), prg_size, 0).is_err());
+    }
+}
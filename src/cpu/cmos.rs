@@ -0,0 +1,268 @@
+use crate::cpu::cdl::CDL_INDIRECT_DATA;
+use crate::cpu::{Cpu, CpuBus};
+
+// WDC 65C02 instructions and the opcodes whose behavior differs from the NMOS 6502
+// (see http://www.6502.org/tutorials/65c02opcodes.html)
+impl<B: CpuBus> Cpu<B> {
+    /// Decimal addition according to http://www.6502.org/tutorials/decimal_mode.html (sequences 1 and 2)
+    pub(super) fn adc_decimal_cmos(&mut self, m: u8) {
+        let a = self.registers.a;
+        let c = if self.registers.get_carry_bit() { 1 } else { 0 };
+
+        let mut lo = (a & 0x0F) as u16 + (m & 0x0F) as u16 + c;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) as u16 + (m & 0xF0) as u16 + lo;
+        let signed_sum = (a & 0xF0) as i8 as i16 + (m & 0xF0) as i8 as i16 + lo as i16;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+
+        self.registers.update_carry_bit(sum >= 0x100);
+        self.registers
+            .update_overflow_bit(!(-128..=127).contains(&signed_sum));
+        // unlike the NMOS 6502, N and Z reflect the decimal result
+        self.registers.update_a(sum as u8);
+
+        // the flags are fixed up in an additional cycle
+        self.dummy_read_next();
+    }
+
+    /// Decimal subtraction according to http://www.6502.org/tutorials/decimal_mode.html (sequence 4)
+    pub(super) fn sbc_decimal_cmos(&mut self, m: u8) {
+        let a = self.registers.a;
+        let c = if self.registers.get_carry_bit() { 1 } else { 0 };
+
+        let lo = (a & 0x0F) as i16 - (m & 0x0F) as i16 + c - 1;
+        let diff = a as i16 - m as i16 + c - 1;
+        let v = ((a ^ m) & (a ^ diff as u8) & 0x80) != 0;
+
+        let mut result = diff;
+        if result < 0 {
+            result -= 0x60;
+        }
+        if lo < 0 {
+            result -= 0x06;
+        }
+
+        self.registers.update_carry_bit(diff >= 0);
+        self.registers.update_overflow_bit(v);
+        self.registers.update_a(result as u8);
+
+        self.dummy_read_next();
+    }
+
+    pub(super) fn addr_indirect_zeropage(&mut self) -> u16 {
+        let addr = self.next();
+        let lo = self.read_memory(u16::from(addr));
+        let hi = self.read_memory(u16::from(addr.wrapping_add(1)));
+        self.data_flags |= CDL_INDIRECT_DATA;
+        u16::from(lo) | (u16::from(hi) << 8)
+    }
+
+    pub(super) fn tsb(&mut self, data: u8) -> u8 {
+        self.registers.update_zero_bit(self.registers.a & data == 0);
+        data | self.registers.a
+    }
+
+    pub(super) fn trb(&mut self, data: u8) -> u8 {
+        self.registers.update_zero_bit(self.registers.a & data == 0);
+        data & !self.registers.a
+    }
+
+    /// Only the zero flag is affected in immediate mode
+    pub(super) fn bit_immediate(&mut self, data: u8) {
+        self.registers.update_zero_bit(self.registers.a & data == 0);
+    }
+
+    pub(super) fn phx(&mut self) {
+        self.dummy_read_next();
+        self.push_stack(self.registers.x);
+    }
+    pub(super) fn phy(&mut self) {
+        self.dummy_read_next();
+        self.push_stack(self.registers.y);
+    }
+    pub(super) fn plx(&mut self) {
+        self.dummy_read_next();
+        self.dummy_read_stack();
+        let data = self.pull_stack();
+        self.registers.update_x(data);
+    }
+    pub(super) fn ply(&mut self) {
+        self.dummy_read_next();
+        self.dummy_read_stack();
+        let data = self.pull_stack();
+        self.registers.update_y(data);
+    }
+
+    pub(super) fn bra(&mut self) {
+        self.branch_on_condition(true);
+    }
+
+    pub(super) fn jmp_absolute_x_indirect(&mut self) {
+        let base = self.addr_absolute();
+        self.dummy_read(self.registers.pc.wrapping_sub(1));
+        let addr = base.wrapping_add(self.registers.x as u16);
+        let lo = self.read_memory(addr);
+        let hi = self.read_memory(addr.wrapping_add(1));
+        self.registers.pc = u16::from(lo) | (u16::from(hi) << 8);
+        self.jumped_indirectly = true;
+    }
+
+    pub(super) fn rmb(&mut self, bit: u8) {
+        let addr = self.addr_zeropage();
+        let data = self.read_memory(addr);
+        self.dummy_read(addr);
+        self.write_memory(addr, data & !(1 << bit));
+    }
+
+    pub(super) fn smb(&mut self, bit: u8) {
+        let addr = self.addr_zeropage();
+        let data = self.read_memory(addr);
+        self.dummy_read(addr);
+        self.write_memory(addr, data | (1 << bit));
+    }
+
+    pub(super) fn bbr(&mut self, bit: u8) {
+        let addr = self.addr_zeropage();
+        let data = self.read_memory(addr);
+        self.dummy_read(addr);
+        self.branch_on_condition(data & (1 << bit) == 0);
+    }
+
+    pub(super) fn bbs(&mut self, bit: u8) {
+        let addr = self.addr_zeropage();
+        let data = self.read_memory(addr);
+        self.dummy_read(addr);
+        self.branch_on_condition(data & (1 << bit) != 0);
+    }
+
+    pub(super) fn wai(&mut self) {
+        self.dummy_read_next();
+        self.dummy_read_next();
+        self.waiting_for_interrupt = true;
+    }
+
+    pub(super) fn stp(&mut self) {
+        self.dummy_read_next();
+        self.dummy_read_next();
+        self.stopped = true;
+    }
+
+    pub(super) fn nop_absolute_8_cycles(&mut self) {
+        self.addr_absolute();
+        for _ in 0..5 {
+            self.clock_cycle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::{Cpu, CpuOptions, CpuVariant};
+    use crate::memory::{Memory, Ram};
+
+    fn run_program(program: &[u8], instructions: usize) -> Cpu<Ram> {
+        let options = CpuOptions {
+            variant: CpuVariant::Wdc65C02,
+            ..Default::default()
+        };
+        let mut cpu = Cpu::new(Ram::new(0x10000), options);
+
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0x0200 + i as u16, *byte);
+        }
+        cpu.registers.pc = 0x0200;
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn store_zero_and_test_bits() {
+        // LDA #$0F, STA $10, STZ $11, TSB $11, LDA #$03, TRB $10
+        let program = [
+            0xA9, 0x0F, 0x85, 0x10, 0x64, 0x11, 0x04, 0x11, 0xA9, 0x03, 0x14, 0x10,
+        ];
+        let cpu = run_program(&program, 6);
+        assert_eq!(cpu.bus.read(0x10), 0x0C);
+        assert_eq!(cpu.bus.read(0x11), 0x0F);
+        assert!(!cpu.registers.get_zero_bit());
+    }
+
+    #[test]
+    fn stack_and_accumulator_instructions() {
+        // LDX #$12, LDY #$34, PHX, PHY, PLX, PLY, LDA #$FF, INC A
+        let program = [
+            0xA2, 0x12, 0xA0, 0x34, 0xDA, 0x5A, 0xFA, 0x7A, 0xA9, 0xFF, 0x1A,
+        ];
+        let cpu = run_program(&program, 8);
+        assert_eq!(cpu.registers.x, 0x34);
+        assert_eq!(cpu.registers.y, 0x12);
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.get_zero_bit());
+    }
+
+    #[test]
+    fn indirect_zeropage_and_branch_always() {
+        // LDA #$00, STA $20, LDA #$03, STA $21, LDA #$5A, STA ($20), BRA +2, ..., LDA #$00, LDA ($20)
+        let program = [
+            0xA9, 0x00, 0x85, 0x20, 0xA9, 0x03, 0x85, 0x21, 0xA9, 0x5A, 0x92, 0x20, 0x80, 0x02,
+            0xFF, 0xFF, 0xA9, 0x00, 0xB2, 0x20,
+        ];
+        let cpu = run_program(&program, 9);
+        assert_eq!(cpu.bus.read(0x0300), 0x5A);
+        assert_eq!(cpu.registers.a, 0x5A);
+        assert_eq!(cpu.registers.pc, 0x0214);
+    }
+
+    #[test]
+    fn indirect_jump_across_page() {
+        // JMP ($02FF) with the target split across $02FF and $0300
+        let program = [0x6C, 0xFF, 0x02];
+        let mut cpu = run_program(&[], 0);
+        cpu.bus.write(0x02FF, 0x34);
+        cpu.bus.write(0x0300, 0x12);
+        cpu.bus.write(0x0200, 0x56);
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0x0210 + i as u16, *byte);
+        }
+        cpu.registers.pc = 0x0210;
+        let start_cycle = cpu.cycle;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x1234);
+        assert_eq!(cpu.cycle - start_cycle, 6);
+    }
+
+    #[test]
+    fn step_stops_at_stp_and_wai() {
+        for opcode in [0xDB, 0xCB] {
+            // STP or WAI, NOP
+            let mut cpu = run_program(&[opcode, 0xEA], 1);
+            let cycle = cpu.cycle;
+            cpu.step().unwrap();
+            assert_eq!(cpu.registers.pc, 0x0201);
+            assert_eq!(cpu.cycle, cycle + 1);
+        }
+    }
+
+    #[test]
+    fn decimal_flags() {
+        // SED, CLC, LDA #$99, ADC #$01
+        let program = [0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01];
+        let cpu = run_program(&program, 4);
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.get_carry_bit());
+        assert!(cpu.registers.get_zero_bit());
+
+        // SED, SEC, LDA #$00, SBC #$01
+        let program = [0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01];
+        let cpu = run_program(&program, 4);
+        assert_eq!(cpu.registers.a, 0x99);
+        assert!(!cpu.registers.get_carry_bit());
+        assert!(cpu.registers.get_negative_bit());
+    }
+}
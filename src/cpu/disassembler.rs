@@ -0,0 +1,232 @@
+use crate::cpu::opcodes::{AddressingMode::*, Opcode, Operation};
+use crate::cpu::{CpuBus, CpuVariant};
+use std::fmt;
+
+/// A single decoded instruction
+pub struct Disassembly {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: &'static Opcode,
+    /// The operand with the effective address and the value there, formatted like nestest.log
+    pub operand: String,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.opcode.official {
+            write!(f, "*")?;
+        }
+        write!(f, "{}", self.opcode.mnemonic())?;
+        if !self.operand.is_empty() {
+            write!(f, " {}", self.operand)?;
+        }
+        Ok(())
+    }
+}
+
+fn peek_word(bus: &impl CpuBus, lo: u16, hi: u16) -> u16 {
+    u16::from(bus.peek(lo)) | (u16::from(bus.peek(hi)) << 8)
+}
+
+/// Decodes the instruction at `addr` without side effects on the bus.
+///
+/// `x` and `y` are the current index registers, they are needed to resolve effective addresses.
+pub fn disassemble(bus: &impl CpuBus, variant: CpuVariant, addr: u16, x: u8, y: u8) -> Disassembly {
+    let opcode = &variant.opcodes()[bus.peek(addr) as usize];
+    let bytes: Vec<u8> = (0..opcode.len())
+        .map(|i| bus.peek(addr.wrapping_add(i)))
+        .collect();
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from(byte) | (u16::from(bytes.get(2).copied().unwrap_or(0)) << 8);
+
+    let operand = match opcode.mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", byte),
+        Zeropage => format!("${:02X} = {:02X}", byte, bus.peek(u16::from(byte))),
+        ZeropageX | ZeropageY => {
+            let (register, index) = if opcode.mode == ZeropageX {
+                ('X', x)
+            } else {
+                ('Y', y)
+            };
+            let ea = u16::from(byte.wrapping_add(index));
+            format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                byte,
+                register,
+                ea,
+                bus.peek(ea)
+            )
+        }
+        Absolute => match opcode.operation {
+            Operation::Jmp | Operation::Jsr => format!("${:04X}", word),
+            _ => format!("${:04X} = {:02X}", word, bus.peek(word)),
+        },
+        AbsoluteX | AbsoluteY => {
+            let (register, index) = if opcode.mode == AbsoluteX {
+                ('X', x)
+            } else {
+                ('Y', y)
+            };
+            let ea = word.wrapping_add(u16::from(index));
+            format!(
+                "${:04X},{} @ {:04X} = {:02X}",
+                word,
+                register,
+                ea,
+                bus.peek(ea)
+            )
+        }
+        Indirect => {
+            let hi = if variant == CpuVariant::Nmos6502 {
+                // the high byte is fetched without carrying into the page
+                (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF)
+            } else {
+                word.wrapping_add(1)
+            };
+            let target = peek_word(bus, word, hi);
+            format!("(${:04X}) = {:04X}", word, target)
+        }
+        PreindexedIndirectZeropageX => {
+            let pointer = byte.wrapping_add(x);
+            let ea = peek_word(bus, u16::from(pointer), u16::from(pointer.wrapping_add(1)));
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                byte,
+                pointer,
+                ea,
+                bus.peek(ea)
+            )
+        }
+        PostindexedIndirectZeropageY => {
+            let base = peek_word(bus, u16::from(byte), u16::from(byte.wrapping_add(1)));
+            let ea = base.wrapping_add(u16::from(y));
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                byte,
+                base,
+                ea,
+                bus.peek(ea)
+            )
+        }
+        Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            format!("${:04X}", target)
+        }
+        IndirectZeropage => {
+            let ea = peek_word(bus, u16::from(byte), u16::from(byte.wrapping_add(1)));
+            format!("(${:02X}) = {:04X} = {:02X}", byte, ea, bus.peek(ea))
+        }
+        AbsoluteXIndirect => {
+            let pointer = word.wrapping_add(u16::from(x));
+            let target = peek_word(bus, pointer, pointer.wrapping_add(1));
+            format!("(${:04X},X) = {:04X}", word, target)
+        }
+        ZeropageRelative => {
+            let ea = u16::from(byte);
+            let offset = bytes.get(2).copied().unwrap_or(0);
+            let target = addr.wrapping_add(3).wrapping_add(offset as i8 as u16);
+            format!("${:02X} = {:02X},${:04X}", byte, bus.peek(ea), target)
+        }
+    };
+
+    Disassembly {
+        addr,
+        bytes,
+        opcode,
+        operand,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::disassembler::disassemble;
+    use crate::cpu::CpuVariant;
+    use crate::memory::{Memory, Ram};
+
+    #[test]
+    fn disassemble_instructions() {
+        let mut memory = Ram::new(0x10000);
+        let program: [(u16, &[u8]); 10] = [
+            (0xC000, &[0xA9, 0x00]),
+            (0xC002, &[0x4A]),
+            (0xC003, &[0xB4, 0x33]),
+            (0xC005, &[0xB9, 0x00, 0x03]),
+            (0xC008, &[0xA1, 0x80]),
+            (0xC00A, &[0xB1, 0x89]),
+            (0xC00C, &[0x6C, 0xFF, 0x02]),
+            (0xC00F, &[0xB0, 0xFC]),
+            (0xC011, &[0x04, 0xA9]),
+            (0xC013, &[0x20, 0x2D, 0xC7]),
+        ];
+        for (addr, bytes) in program {
+            for (i, &byte) in bytes.iter().enumerate() {
+                memory.write(addr + i as u16, byte);
+            }
+        }
+        memory.write(0x0080, 0x00);
+        memory.write(0x0081, 0x02);
+        memory.write(0x0089, 0x00);
+        memory.write(0x008A, 0x03);
+        memory.write(0x0200, 0x03);
+        memory.write(0x02FF, 0x00);
+        memory.write(0x0300, 0x89);
+
+        let expected = [
+            "LDA #$00",
+            "LSR A",
+            "LDY $33,X @ 33 = 00",
+            "LDA $0300,Y @ 0300 = 89",
+            "LDA ($80,X) @ 80 = 0200 = 03",
+            "LDA ($89),Y = 0300 @ 0300 = 89",
+            "JMP ($02FF) = 0300",
+            "BCS $C00D",
+            "*NOP $A9 = 00",
+            "JSR $C72D",
+        ];
+        for ((addr, bytes), expected) in program.into_iter().zip(expected) {
+            let disassembly = disassemble(&memory, CpuVariant::Nmos6502, addr, 0, 0);
+            assert_eq!(disassembly.to_string(), expected);
+            assert_eq!(disassembly.bytes, bytes);
+        }
+    }
+
+    #[test]
+    fn disassemble_cmos_instructions() {
+        let mut memory = Ram::new(0x10000);
+        let program: [(u16, &[u8]); 5] = [
+            (0x0400, &[0xB2, 0x10]),
+            (0x0402, &[0x7C, 0x00, 0x03]),
+            (0x0405, &[0x3F, 0x10, 0xFB]),
+            (0x0408, &[0x03]),
+            (0x0409, &[0x6C, 0xFF, 0x02]),
+        ];
+        for (addr, bytes) in program {
+            for (i, &byte) in bytes.iter().enumerate() {
+                memory.write(addr + i as u16, byte);
+            }
+        }
+        memory.write(0x0010, 0x00);
+        memory.write(0x0011, 0x02);
+        memory.write(0x0200, 0x42);
+        memory.write(0x0302, 0x34);
+        memory.write(0x0303, 0x12);
+        memory.write(0x02FF, 0x78);
+        memory.write(0x0300, 0x56);
+
+        let expected = [
+            "LDA ($10) = 0200 = 42",
+            "JMP ($0300,X) = 1234",
+            "BBR3 $10 = 00,$0403",
+            "*NOP",
+            // the high byte comes from the next page
+            "JMP ($02FF) = 5678",
+        ];
+        for ((addr, bytes), expected) in program.into_iter().zip(expected) {
+            let disassembly = disassemble(&memory, CpuVariant::Wdc65C02, addr, 2, 0);
+            assert_eq!(disassembly.to_string(), expected);
+            assert_eq!(disassembly.bytes, bytes);
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use crate::cpu::breakpoints::{BreakHit, Breakpoint};
+use crate::cpu::{Cpu, CpuBus};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const INTERRUPT: u8 = 0x03;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.emurs.6502">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8"/>
+    <reg name="y" bitsize="8"/>
+    <reg name="p" bitsize="8"/>
+    <reg name="s" bitsize="8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+enum Input {
+    Packet(String),
+    Interrupt,
+}
+
+/// Server for the GDB remote serial protocol.
+///
+/// The registers are exposed in the order A, X, Y, P, S (8 bit each) and PC (16 bit, little
+/// endian). The stub never blocks, `poll` has to be called regularly and the CPU must not be
+/// ticked while the debugger holds it.
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    halted: bool,
+    /// The breakpoints set by the debugger, the monitor keeps its own ones after detaching
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl GdbStub {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            stream: None,
+            buffer: Vec::new(),
+            halted: false,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Whether the debugger holds the CPU
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Accepts a connection, handles all pending packets and reports breakpoint hits
+    pub fn poll<B: CpuBus>(&mut self, cpu: &mut Cpu<B>) {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    println!("GDB connected from {}", addr);
+                    self.stream = Some(stream);
+                    self.buffer.clear();
+                    // the debugger expects the target to be stopped after attaching
+                    self.halted = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("Failed to accept GDB connection: {}", e);
+                    return;
+                }
+            }
+        }
+
+        if let Err(e) = self.receive(cpu) {
+            println!("GDB disconnected: {}", e);
+            self.detach(cpu);
+            return;
+        }
+
+        if !self.halted && (cpu.break_hit().is_some() || cpu.halted().is_some()) {
+            self.halted = true;
+            let reply = cpu_stop_reply(cpu);
+            if let Err(e) = self.send(&reply) {
+                println!("GDB disconnected: {}", e);
+                self.detach(cpu);
+            }
+        }
+    }
+
+    fn detach<B: CpuBus>(&mut self, cpu: &mut Cpu<B>) {
+        self.stream = None;
+        self.halted = false;
+        for breakpoint in self.breakpoints.drain(..) {
+            cpu.breakpoints_mut().remove(breakpoint);
+        }
+        cpu.resume();
+    }
+
+    fn receive<B: CpuBus>(&mut self, cpu: &mut Cpu<B>) -> io::Result<()> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(());
+        };
+
+        let mut chunk = [0u8; 1024];
+        stream.set_nonblocking(true)?;
+        let result = loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => break Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        result?;
+
+        while let Some(input) = self.next_input()? {
+            match input {
+                Input::Interrupt => {
+                    if !self.halted {
+                        self.halted = true;
+                        self.send(&stop_reply(None, SIGINT))?;
+                    }
+                }
+                Input::Packet(packet) if packet == "D" => {
+                    self.send("OK")?;
+                    self.detach(cpu);
+                    return Ok(());
+                }
+                Input::Packet(packet) if packet == "k" => {
+                    self.detach(cpu);
+                    return Ok(());
+                }
+                Input::Packet(packet) => {
+                    if let Some(reply) = self.handle(&packet, cpu) {
+                        self.send(&reply)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the next packet or interrupt out of the buffer and acknowledges it
+    fn next_input(&mut self) -> io::Result<Option<Input>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(&INTERRUPT) => {
+                    self.buffer.remove(0);
+                    return Ok(Some(Input::Interrupt));
+                }
+                Some(b'$') => {
+                    let Some(end) = self.buffer.iter().position(|&b| b == b'#') else {
+                        return Ok(None);
+                    };
+                    if self.buffer.len() < end + 3 {
+                        return Ok(None);
+                    }
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|s| u8::from_str_radix(s, 16).ok());
+
+                    if checksum == Some(checksum_of(data)) {
+                        self.write_raw(b"+")?;
+                        return Ok(Some(Input::Packet(
+                            String::from_utf8_lossy(data).into_owned(),
+                        )));
+                    }
+                    self.write_raw(b"-")?;
+                }
+                // acknowledgements
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_all(data),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, checksum_of(reply.as_bytes()));
+        self.write_raw(packet.as_bytes())
+    }
+
+    /// Returns the reply to a packet, `None` if the reply is sent once the CPU stops
+    fn handle<B: CpuBus>(&mut self, packet: &str, cpu: &mut Cpu<B>) -> Option<String> {
+        let command = packet.chars().next()?;
+        let args = &packet[command.len_utf8()..];
+        let reply = match command {
+            '?' => cpu_stop_reply(cpu),
+            'g' => to_hex(&registers(cpu)),
+            'G' => match from_hex(args) {
+                Some(values) if values.len() == 7 => {
+                    set_registers(cpu, &values);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            'p' => match parse_hex(args).and_then(|n| register_range(n as usize)) {
+                Some(range) => to_hex(&registers(cpu)[range]),
+                None => "E01".to_string(),
+            },
+            'P' => {
+                let (n, value) = args.split_once('=').unwrap_or_default();
+                let range = parse_hex(n).and_then(|n| register_range(n as usize));
+                match (range, from_hex(value)) {
+                    (Some(range), Some(value)) if range.len() == value.len() => {
+                        let mut values = registers(cpu);
+                        values[range].copy_from_slice(&value);
+                        set_registers(cpu, &values);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'm' => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let data: Vec<u8> = (0..len)
+                        .map(|i| cpu.bus.peek(addr.wrapping_add(i)))
+                        .collect();
+                    to_hex(&data)
+                }
+                None => "E01".to_string(),
+            },
+            'M' => {
+                let (addr_len, data) = args.split_once(':').unwrap_or_default();
+                match (parse_addr_len(addr_len), from_hex(data)) {
+                    (Some((addr, len)), Some(data)) if data.len() == len as usize => {
+                        for (i, value) in data.into_iter().enumerate() {
+                            cpu.bus.write(addr.wrapping_add(i as u16), value);
+                        }
+                        cpu.invalidate_block_cache();
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'c' => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.registers.pc = addr;
+                }
+                cpu.resume();
+                self.halted = false;
+                return None;
+            }
+            's' => {
+                if let Some(addr) = parse_hex(args) {
+                    cpu.registers.pc = addr;
+                }
+                cpu.resume();
+                // a failed step halts the CPU, which is reported below
+                let _ = cpu.tick();
+                cpu_stop_reply(cpu)
+            }
+            'Z' | 'z' => match parse_breakpoints(args) {
+                Some(breakpoints) => {
+                    for breakpoint in breakpoints {
+                        if command == 'Z' {
+                            if cpu.breakpoints_mut().add(breakpoint) {
+                                self.breakpoints.push(breakpoint);
+                            }
+                        } else if self.breakpoints.contains(&breakpoint) {
+                            self.breakpoints.retain(|b| *b != breakpoint);
+                            cpu.breakpoints_mut().remove(breakpoint);
+                        }
+                    }
+                    "OK".to_string()
+                }
+                None => String::new(),
+            },
+            'H' => "OK".to_string(),
+            'q' => query(args),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        "PacketSize=1000;qXfer:features:read+".to_string()
+    } else if args == "Attached" {
+        "1".to_string()
+    } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        match parse_addr_len(range) {
+            Some((offset, len)) => {
+                let start = (offset as usize).min(TARGET_XML.len());
+                let end = (start + len as usize).min(TARGET_XML.len());
+                let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                format!("{}{}", marker, &TARGET_XML[start..end])
+            }
+            None => "E01".to_string(),
+        }
+    } else {
+        String::new()
+    }
+}
+
+/// Reports why the CPU stopped, an illegal instruction if it is halted by an error
+fn cpu_stop_reply<B: CpuBus>(cpu: &Cpu<B>) -> String {
+    match cpu.halted() {
+        Some(_) => stop_reply(None, SIGILL),
+        None => stop_reply(cpu.break_hit(), SIGTRAP),
+    }
+}
+
+fn stop_reply(hit: Option<&BreakHit>, signal: u8) -> String {
+    match hit.map(|hit| (hit.breakpoint, hit.addr)) {
+        Some((Breakpoint::Write(_), addr)) => format!("T{:02x}watch:{:x};", signal, addr),
+        Some((Breakpoint::Read(_), addr)) => format!("T{:02x}rwatch:{:x};", signal, addr),
+        _ => format!("S{:02x}", signal),
+    }
+}
+
+fn registers<B: CpuBus>(cpu: &Cpu<B>) -> [u8; 7] {
+    let r = cpu.registers();
+    [r.a, r.x, r.y, r.p, r.s, r.pc as u8, (r.pc >> 8) as u8]
+}
+
+fn set_registers<B: CpuBus>(cpu: &mut Cpu<B>, values: &[u8]) {
+    let r = cpu.registers_mut();
+    r.a = values[0];
+    r.x = values[1];
+    r.y = values[2];
+    r.p = values[3];
+    r.s = values[4];
+    r.pc = u16::from(values[5]) | (u16::from(values[6]) << 8);
+}
+
+/// Bytes of a register in the `g` packet
+fn register_range(n: usize) -> Option<std::ops::Range<usize>> {
+    match n {
+        0..=4 => Some(n..n + 1),
+        5 => Some(5..7),
+        _ => None,
+    }
+}
+
+/// Parses the `type,addr,kind` arguments of a breakpoint packet
+fn parse_breakpoints(args: &str) -> Option<Vec<Breakpoint>> {
+    let mut parts = args.split(',');
+    let kind = parts.next()?;
+    let addr = parse_hex(parts.next()?)?;
+    let len = parse_hex(parts.next()?)?;
+    let addrs = (0..len.max(1)).map(|i| addr.wrapping_add(i));
+    let breakpoints = match kind {
+        "0" | "1" => vec![Breakpoint::Execute(addr)],
+        "2" => addrs.map(Breakpoint::Write).collect(),
+        "3" => addrs.map(Breakpoint::Read).collect(),
+        "4" => addrs
+            .flat_map(|a| [Breakpoint::Read(a), Breakpoint::Write(a)])
+            .collect(),
+        _ => return None,
+    };
+    Some(breakpoints)
+}
+
+fn parse_addr_len(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn parse_hex(value: &str) -> Option<u16> {
+    u16::from_str_radix(value, 16).ok()
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::breakpoints::Breakpoint;
+    use crate::cpu::gdb::{checksum_of, GdbStub};
+    use crate::cpu::{Cpu, CpuOptions};
+    use crate::memory::{Memory, Ram};
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpStream;
+
+    /// Sends a packet and polls the stub until the reply has arrived
+    fn exchange(
+        client: &mut TcpStream,
+        stub: &mut GdbStub,
+        cpu: &mut Cpu<Ram>,
+        packet: &str,
+    ) -> String {
+        write!(client, "${}#{:02x}", packet, checksum_of(packet.as_bytes())).unwrap();
+        receive(client, stub, cpu)
+    }
+
+    fn receive(client: &mut TcpStream, stub: &mut GdbStub, cpu: &mut Cpu<Ram>) -> String {
+        let mut received = Vec::new();
+        let mut chunk = [0u8; 256];
+        loop {
+            stub.poll(cpu);
+            match client.read(&mut chunk) {
+                Ok(n) => received.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+            let text = String::from_utf8_lossy(&received).into_owned();
+            let text = text.trim_start_matches('+');
+            if let Some(end) = text.find('#') {
+                if text.len() >= end + 3 {
+                    return text[1..end].to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn debug_session() {
+        let mut memory = Ram::new(0x10000);
+        // LDA #$42, STA $10, NOP
+        for (i, opcode) in [0xA9, 0x42, 0x85, 0x10, 0xEA].into_iter().enumerate() {
+            memory.write(0x0400 + i as u16, opcode);
+        }
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.registers.pc = 0x0400;
+
+        let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "?"), "S05");
+        assert!(stub.is_halted());
+        assert_eq!(
+            exchange(&mut client, &mut stub, &mut cpu, "g"),
+            "00000000000004"
+        );
+        assert_eq!(
+            exchange(&mut client, &mut stub, &mut cpu, "m400,3"),
+            "a94285"
+        );
+        assert_eq!(
+            exchange(&mut client, &mut stub, &mut cpu, "M20,2:beef"),
+            "OK"
+        );
+        assert_eq!(Memory::read(&cpu.bus, 0x21), 0xEF);
+
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "s"), "S05");
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "p0"), "42");
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "p5"), "0204");
+
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "Z0,404,1"), "OK");
+        write!(client, "$c#{:02x}", checksum_of(b"c")).unwrap();
+        while stub.is_halted() {
+            stub.poll(&mut cpu);
+        }
+        while !stub.is_halted() {
+            cpu.tick().unwrap();
+            stub.poll(&mut cpu);
+        }
+        assert_eq!(receive(&mut client, &mut stub, &mut cpu), "S05");
+        assert_eq!(cpu.registers.pc, 0x0404);
+        assert_eq!(Memory::read(&cpu.bus, 0x10), 0x42);
+
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "P5=0004"), "OK");
+        assert_eq!(cpu.registers.pc, 0x0400);
+    }
+
+    #[test]
+    fn detach_keeps_other_breakpoints() {
+        let mut cpu = Cpu::new(Ram::new(0x10000), CpuOptions::default());
+        cpu.breakpoints_mut().add(Breakpoint::Execute(0x0400));
+        cpu.breakpoints_mut().add(Breakpoint::Write(0x0010));
+
+        let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+
+        // the first one was set by the monitor, GDB neither owns nor removes it
+        for packet in ["Z0,400,1", "Z0,402,1", "Z2,20,1", "z0,400,1"] {
+            assert_eq!(exchange(&mut client, &mut stub, &mut cpu, packet), "OK");
+        }
+        assert_eq!(exchange(&mut client, &mut stub, &mut cpu, "D"), "OK");
+        assert!(!stub.is_halted());
+
+        let breakpoints: Vec<_> = cpu.breakpoints().iter().copied().collect();
+        assert_eq!(
+            breakpoints,
+            [Breakpoint::Execute(0x0400), Breakpoint::Write(0x0010)]
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use crate::cpu::breakpoints::Breakpoint;
+use crate::cpu::bus::Bus;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::ppu::OAM_SIZE;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const HELP: &str = "\
+Addresses and values are hexadecimal, counts are decimal
+  h, help                   show this help
+  p, pause                  pause the emulation
+  c, continue               continue the emulation
+  s, step [count]           execute instructions
+  u, until <addr>           run until the instruction at the address
+  r, regs                   show the registers
+  reset                     reset the CPU
+  m, mem <start> [end]      dump CPU memory
+  v, vram <start> [end]     dump PPU memory
+  d, disasm [addr] [count]  disassemble CPU memory, starting at PC by default
+  w, poke <addr> <value>..  write bytes to CPU memory
+  vw, vpoke <addr> <value>.. write bytes to PPU memory
+  oam                       show the sprites in OAM
+  b, break <addr>           break before executing the address
+  br <addr>                 break on reads of the address
+  bw <addr>                 break on writes to the address
+  bpr <register>            break on reads of a PPU register, e.g. bpr 2002
+  bpw <register>            break on writes to a PPU register, e.g. bpw 2006
+  bl                        list breakpoints
+  bd <index>                delete a breakpoint
+";
+
+const DEFAULT_DUMP_LENGTH: u16 = 0x80;
+const DEFAULT_DISASSEMBLY_LENGTH: usize = 10;
+
+/// A text monitor reading commands from stdin, output goes to stdout
+pub struct Monitor {
+    input: Receiver<String>,
+    paused: bool,
+    /// The temporary breakpoint of `until`
+    run_to: Option<u16>,
+}
+
+impl Monitor {
+    /// Starts reading commands from stdin on a background thread
+    pub fn spawn() -> Self {
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self::new(input)
+    }
+
+    fn new(input: Receiver<String>) -> Self {
+        Self {
+            input,
+            paused: false,
+            run_to: None,
+        }
+    }
+
+    /// Whether the emulation has been paused from the monitor
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Executes all pending commands and pauses when a breakpoint was hit
+    pub fn poll(&mut self, cpu: &mut Cpu<Bus>) {
+        if !self.paused && (cpu.break_hit().is_some() || cpu.halted().is_some()) {
+            self.paused = true;
+            print!("{}", self.report_break(cpu));
+        }
+        while let Ok(line) = self.input.try_recv() {
+            print!("{}", self.execute(&line, cpu));
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    fn report_break(&mut self, cpu: &mut Cpu<Bus>) -> String {
+        if let Some(error) = cpu.halted() {
+            return format!("{}, reset to continue\n{}", error, status(cpu));
+        }
+        let Some(hit) = cpu.break_hit() else {
+            return String::new();
+        };
+        let mut out = String::new();
+        match self.run_to {
+            Some(addr) if hit.breakpoint == Breakpoint::Execute(addr) => {
+                cpu.breakpoints_mut().remove(Breakpoint::Execute(addr));
+                self.run_to = None;
+                let _ = writeln!(out, "Reached ${:04X}", addr);
+                out += &status(cpu);
+            }
+            _ => {
+                let _ = writeln!(out, "{}", hit);
+                let _ = writeln!(out, "{}", disassembly_line(cpu, cpu.registers.pc));
+            }
+        }
+        out
+    }
+
+    /// Executes a single command and returns its output
+    pub fn execute(&mut self, line: &str, cpu: &mut Cpu<Bus>) -> String {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "h" | "help" => Ok(HELP.to_string()),
+            "p" | "pause" => {
+                self.paused = true;
+                Ok(status(cpu))
+            }
+            "c" | "continue" => {
+                self.paused = false;
+                cpu.resume();
+                Ok(String::new())
+            }
+            "s" | "step" => parse_count(args.first(), 1).map(|count| self.step(cpu, count)),
+            "u" | "until" => parse_addr(args.first()).map(|addr| {
+                if !cpu
+                    .breakpoints()
+                    .iter()
+                    .any(|b| *b == Breakpoint::Execute(addr))
+                {
+                    cpu.breakpoints_mut().add(Breakpoint::Execute(addr));
+                    self.run_to = Some(addr);
+                }
+                self.paused = false;
+                cpu.resume();
+                String::new()
+            }),
+            "r" | "regs" => Ok(status(cpu)),
+            "reset" => {
+                cpu.reset();
+                Ok(status(cpu))
+            }
+            "m" | "mem" => {
+                parse_range(&args).map(|(start, end)| dump(start, end, |a| cpu.bus.peek(a)))
+            }
+            "v" | "vram" => parse_range(&args)
+                .map(|(start, end)| dump(start & 0x3FFF, end & 0x3FFF, |a| peek_ppu(&cpu.bus, a))),
+            "d" | "disasm" => {
+                let start = match args.first() {
+                    Some(_) => parse_addr(args.first()),
+                    None => Ok(cpu.registers.pc),
+                };
+                let count = parse_count(args.get(1), DEFAULT_DISASSEMBLY_LENGTH);
+                start.and_then(|start| count.map(|count| disassemble(cpu, start, count)))
+            }
+            "w" | "poke" => parse_poke(&args).map(|(addr, values)| {
+                for (i, value) in values.into_iter().enumerate() {
+                    cpu.bus.write(addr.wrapping_add(i as u16), value);
+                }
+                cpu.invalidate_block_cache();
+                String::new()
+            }),
+            "vw" | "vpoke" => parse_poke(&args).map(|(addr, values)| {
+                for (i, value) in values.into_iter().enumerate() {
+                    let addr = addr.wrapping_add(i as u16) & 0x3FFF;
+                    if (0x2000..0x3000).contains(&addr) || addr >= 0x3F00 {
+                        cpu.bus.ppu.memory.write(addr, value);
+                    }
+                }
+                String::new()
+            }),
+            "oam" => Ok(oam(cpu)),
+            "b" | "break" => {
+                parse_addr(args.first()).map(|addr| add_breakpoint(cpu, Breakpoint::Execute(addr)))
+            }
+            "br" => {
+                parse_addr(args.first()).map(|addr| add_breakpoint(cpu, Breakpoint::Read(addr)))
+            }
+            "bw" => {
+                parse_addr(args.first()).map(|addr| add_breakpoint(cpu, Breakpoint::Write(addr)))
+            }
+            "bpr" => parse_ppu_register(args.first())
+                .map(|register| add_breakpoint(cpu, Breakpoint::PpuRegisterRead(register))),
+            "bpw" => parse_ppu_register(args.first())
+                .map(|register| add_breakpoint(cpu, Breakpoint::PpuRegisterWrite(register))),
+            "bl" => Ok(cpu
+                .breakpoints()
+                .iter()
+                .enumerate()
+                .map(|(i, b)| format!("{}: {}\n", i, b))
+                .collect()),
+            "bd" => parse_count(args.first(), 0).and_then(|idx| {
+                let breakpoint = cpu.breakpoints().iter().nth(idx).copied();
+                match breakpoint {
+                    Some(breakpoint) => {
+                        cpu.breakpoints_mut().remove(breakpoint);
+                        Ok(String::new())
+                    }
+                    None => Err(format!("No breakpoint {}", idx)),
+                }
+            }),
+            _ => Err(format!("Unknown command {}, try help", command)),
+        };
+
+        match result {
+            Ok(out) => out,
+            Err(e) => e + "\n",
+        }
+    }
+
+    fn step(&mut self, cpu: &mut Cpu<Bus>, count: usize) -> String {
+        self.paused = true;
+        for _ in 0..count {
+            cpu.resume();
+            if cpu.tick().is_err() || cpu.break_hit().is_some() {
+                return self.report_break(cpu);
+            }
+        }
+        status(cpu)
+    }
+}
+
+fn status(cpu: &Cpu<Bus>) -> String {
+    let mut cycle = format!("CYC:{}", cpu.cycle());
+    let interrupts = cpu.pending_interrupts();
+    if interrupts.nmi {
+        cycle += "  NMI pending";
+    }
+    if interrupts.irq {
+        cycle += "  IRQ asserted";
+    }
+    format!(
+        "{}\n{}\n{}\n",
+        cpu.registers(),
+        cycle,
+        disassembly_line(cpu, cpu.registers().pc)
+    )
+}
+
+fn disassembly_line(cpu: &Cpu<Bus>, addr: u16) -> String {
+    let disassembly = cpu.disassemble(addr);
+    let bytes: Vec<String> = disassembly
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!("{:04X}  {:<8}  {}", addr, bytes.join(" "), disassembly)
+}
+
+fn disassemble(cpu: &Cpu<Bus>, start: u16, count: usize) -> String {
+    let mut out = String::new();
+    let mut addr = start;
+    for _ in 0..count {
+        let _ = writeln!(out, "{}", disassembly_line(cpu, addr));
+        addr = addr.wrapping_add(cpu.disassemble(addr).opcode.len());
+    }
+    out
+}
+
+/// Reads PPU memory without panicking on unmapped addresses
+fn peek_ppu(bus: &Bus, addr: u16) -> u8 {
+    let memory = &bus.ppu.memory;
+    match addr {
+        0x0000..0x2000 => memory.mapper.read_chr(addr),
+        // $3000-$3EFF mirrors the nametables
+        0x3000..0x3F00 => memory.read(addr - 0x1000),
+        _ => memory.read(addr),
+    }
+}
+
+fn dump(start: u16, end: u16, mut read: impl FnMut(u16) -> u8) -> String {
+    let mut out = String::new();
+    for line_start in (start..=end).step_by(16) {
+        let _ = write!(out, "{:04X}:", line_start);
+        for addr in line_start..=end.min(line_start.saturating_add(15)) {
+            let _ = write!(out, " {:02X}", read(addr));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn oam(cpu: &Cpu<Bus>) -> String {
+    let oam = &cpu.bus.ppu.oam;
+    let mut out = String::new();
+    for (i, sprite) in oam.chunks(4).enumerate().take(OAM_SIZE / 4) {
+        let _ = writeln!(
+            out,
+            "{:02}: X:{:02X} Y:{:02X} Tile:{:02X} Attributes:{:02X}",
+            i, sprite[3], sprite[0], sprite[1], sprite[2]
+        );
+    }
+    out
+}
+
+fn add_breakpoint(cpu: &mut Cpu<Bus>, breakpoint: Breakpoint) -> String {
+    cpu.breakpoints_mut().add(breakpoint);
+    format!("Added {}\n", breakpoint)
+}
+
+fn parse_addr(arg: Option<&&str>) -> Result<u16, String> {
+    let arg = arg.ok_or("Missing address")?;
+    u16::from_str_radix(arg.trim_start_matches('$'), 16)
+        .map_err(|_| format!("Invalid address {}", arg))
+}
+
+fn parse_ppu_register(arg: Option<&&str>) -> Result<u8, String> {
+    match parse_addr(arg)? {
+        addr @ 0x2000..=0x2007 => Ok((addr & 0x7) as u8),
+        addr => Err(format!("${:04X} is not a PPU register", addr)),
+    }
+}
+
+fn parse_count(arg: Option<&&str>, default: usize) -> Result<usize, String> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| format!("Invalid count {}", arg)),
+        None => Ok(default),
+    }
+}
+
+fn parse_range(args: &[&str]) -> Result<(u16, u16), String> {
+    let start = parse_addr(args.first())?;
+    let end = match args.get(1) {
+        Some(_) => parse_addr(args.get(1))?,
+        None => start.saturating_add(DEFAULT_DUMP_LENGTH - 1),
+    };
+    if end < start {
+        return Err("The end of the range is before its start".to_string());
+    }
+    Ok((start, end))
+}
+
+fn parse_poke(args: &[&str]) -> Result<(u16, Vec<u8>), String> {
+    let addr = parse_addr(args.first())?;
+    let values = args[1..]
+        .iter()
+        .map(|arg| {
+            u8::from_str_radix(arg.trim_start_matches('$'), 16)
+                .map_err(|_| format!("Invalid value {}", arg))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if values.is_empty() {
+        return Err("Missing value".to_string());
+    }
+    Ok((addr, values))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::bus::Bus;
+    use crate::cpu::monitor::Monitor;
+    use crate::cpu::Cpu;
+    use crate::nes_rom::NesRom;
+    use std::sync::mpsc;
+
+    fn setup() -> (Monitor, Cpu<Bus>) {
+        let rom = NesRom::read_from_file("./vendor/nestest/nestest.nes").unwrap();
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+        cpu.registers.pc = 0x0200;
+        let (_, input) = mpsc::channel();
+        (Monitor::new(input), cpu)
+    }
+
+    #[test]
+    fn memory_commands() {
+        let (mut monitor, mut cpu) = setup();
+
+        // LDA #$42, STA $10, NOP
+        assert_eq!(monitor.execute("w 200 A9 42 85 10 EA", &mut cpu), "");
+        assert_eq!(
+            monitor.execute("m 200 204", &mut cpu),
+            "0200: A9 42 85 10 EA\n"
+        );
+        assert_eq!(
+            monitor.execute("d $200 2", &mut cpu),
+            "0200  A9 42     LDA #$42\n0202  85 10     STA $10 = 00\n"
+        );
+
+        monitor.execute("vw 2000 12 34", &mut cpu);
+        assert_eq!(monitor.execute("v 3000 3001", &mut cpu), "3000: 12 34\n");
+
+        assert_eq!(monitor.execute("m 200 1ff", &mut cpu).lines().count(), 1);
+        assert_eq!(monitor.execute("w zz 1", &mut cpu), "Invalid address zz\n");
+    }
+
+    #[test]
+    fn execution_commands() {
+        let (mut monitor, mut cpu) = setup();
+        monitor.execute("w 200 A9 42 85 10 EA EA", &mut cpu);
+
+        let out = monitor.execute("s 2", &mut cpu);
+        assert!(monitor.is_paused());
+        assert!(out.contains("A:42"));
+        assert!(out.ends_with("0204  EA        NOP\n"));
+        assert_eq!(cpu.bus.read(0x10), 0x42);
+
+        monitor.execute("u 205", &mut cpu);
+        assert!(!monitor.is_paused());
+        while cpu.break_hit().is_none() {
+            cpu.tick().unwrap();
+        }
+        monitor.poll(&mut cpu);
+        assert!(monitor.is_paused());
+        assert_eq!(cpu.registers.pc, 0x0205);
+        assert_eq!(monitor.execute("bl", &mut cpu), "");
+
+        monitor.execute("bpw 2006", &mut cpu);
+        assert_eq!(
+            monitor.execute("bl", &mut cpu),
+            "0: write to PPU register $2006\n"
+        );
+        monitor.execute("bd 0", &mut cpu);
+        assert_eq!(monitor.execute("bl", &mut cpu), "");
+    }
+}
@@ -0,0 +1,688 @@
+use AddressingMode::*;
+use Operation::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    Zeropage,
+    ZeropageX,
+    ZeropageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    PreindexedIndirectZeropageX,
+    PostindexedIndirectZeropageY,
+    Relative,
+    /// (zp) of the 65C02
+    IndirectZeropage,
+    /// (abs,X) of the 65C02 JMP
+    AbsoluteXIndirect,
+    /// A zeropage address followed by a branch offset, used by BBR and BBS
+    ZeropageRelative,
+}
+
+impl AddressingMode {
+    /// Length of the instruction in bytes, including the opcode
+    pub const fn len(self) -> u16 {
+        match self {
+            Implied | Accumulator => 1,
+            Immediate
+            | Zeropage
+            | ZeropageX
+            | ZeropageY
+            | PreindexedIndirectZeropageX
+            | PostindexedIndirectZeropageY
+            | Relative
+            | IndirectZeropage => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect | AbsoluteXIndirect | ZeropageRelative => 3,
+        }
+    }
+}
+
+/// What an instruction does, independent of its addressing mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+
+    // unofficial NMOS opcodes
+    Alr,
+    Anc,
+    Arr,
+    Axs,
+    Dcp,
+    Isb,
+    /// Locks up the CPU
+    Jam,
+    Las,
+    Lax,
+    Rla,
+    Rra,
+    Sax,
+    Slo,
+    Sre,
+    // the unstable ones are not emulated
+    Sha,
+    Shx,
+    Shy,
+    Tas,
+    Xaa,
+
+    // 65C02 instructions
+    Bbr(u8),
+    Bbs(u8),
+    Bra,
+    Phx,
+    Phy,
+    Plx,
+    Ply,
+    Rmb(u8),
+    Smb(u8),
+    Stp,
+    Stz,
+    Trb,
+    Tsb,
+    Wai,
+    /// The undefined 65C02 opcodes that only take the cycle of the opcode fetch
+    NopSingleCycle,
+    /// The undefined 65C02 opcode $5C, an absolute read followed by idle cycles
+    NopEightCycles,
+}
+
+impl Operation {
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Adc => "ADC",
+            And => "AND",
+            Asl => "ASL",
+            Bcc => "BCC",
+            Bcs => "BCS",
+            Beq => "BEQ",
+            Bit => "BIT",
+            Bmi => "BMI",
+            Bne => "BNE",
+            Bpl => "BPL",
+            Brk => "BRK",
+            Bvc => "BVC",
+            Bvs => "BVS",
+            Clc => "CLC",
+            Cld => "CLD",
+            Cli => "CLI",
+            Clv => "CLV",
+            Cmp => "CMP",
+            Cpx => "CPX",
+            Cpy => "CPY",
+            Dec => "DEC",
+            Dex => "DEX",
+            Dey => "DEY",
+            Eor => "EOR",
+            Inc => "INC",
+            Inx => "INX",
+            Iny => "INY",
+            Jmp => "JMP",
+            Jsr => "JSR",
+            Lda => "LDA",
+            Ldx => "LDX",
+            Ldy => "LDY",
+            Lsr => "LSR",
+            Nop | NopSingleCycle | NopEightCycles => "NOP",
+            Ora => "ORA",
+            Pha => "PHA",
+            Php => "PHP",
+            Pla => "PLA",
+            Plp => "PLP",
+            Rol => "ROL",
+            Ror => "ROR",
+            Rti => "RTI",
+            Rts => "RTS",
+            Sbc => "SBC",
+            Sec => "SEC",
+            Sed => "SED",
+            Sei => "SEI",
+            Sta => "STA",
+            Stx => "STX",
+            Sty => "STY",
+            Tax => "TAX",
+            Tay => "TAY",
+            Tsx => "TSX",
+            Txa => "TXA",
+            Txs => "TXS",
+            Tya => "TYA",
+            Alr => "ALR",
+            Anc => "ANC",
+            Arr => "ARR",
+            Axs => "AXS",
+            Dcp => "DCP",
+            Isb => "ISB",
+            Jam => "JAM",
+            Las => "LAS",
+            Lax => "LAX",
+            Rla => "RLA",
+            Rra => "RRA",
+            Sax => "SAX",
+            Slo => "SLO",
+            Sre => "SRE",
+            Sha => "SHA",
+            Shx => "SHX",
+            Shy => "SHY",
+            Tas => "TAS",
+            Xaa => "XAA",
+            Bbr(bit) => BIT_MNEMONICS[0][bit as usize],
+            Bbs(bit) => BIT_MNEMONICS[1][bit as usize],
+            Rmb(bit) => BIT_MNEMONICS[2][bit as usize],
+            Smb(bit) => BIT_MNEMONICS[3][bit as usize],
+            Bra => "BRA",
+            Phx => "PHX",
+            Phy => "PHY",
+            Plx => "PLX",
+            Ply => "PLY",
+            Stp => "STP",
+            Stz => "STZ",
+            Trb => "TRB",
+            Tsb => "TSB",
+            Wai => "WAI",
+        }
+    }
+}
+
+const BIT_MNEMONICS: [[&str; 8]; 4] = [
+    [
+        "BBR0", "BBR1", "BBR2", "BBR3", "BBR4", "BBR5", "BBR6", "BBR7",
+    ],
+    [
+        "BBS0", "BBS1", "BBS2", "BBS3", "BBS4", "BBS5", "BBS6", "BBS7",
+    ],
+    [
+        "RMB0", "RMB1", "RMB2", "RMB3", "RMB4", "RMB5", "RMB6", "RMB7",
+    ],
+    [
+        "SMB0", "SMB1", "SMB2", "SMB3", "SMB4", "SMB5", "SMB6", "SMB7",
+    ],
+];
+
+/// An entry of the opcode table, the CPU executes `operation` with the operand that `mode`
+/// resolves to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opcode {
+    pub operation: Operation,
+    pub mode: AddressingMode,
+    /// Cycles without the penalties for page crossings and taken branches
+    pub cycles: u8,
+    pub official: bool,
+}
+
+impl Opcode {
+    const fn official(operation: Operation, mode: AddressingMode, cycles: u8) -> Self {
+        Self {
+            operation,
+            mode,
+            cycles,
+            official: true,
+        }
+    }
+
+    const fn unofficial(operation: Operation, mode: AddressingMode, cycles: u8) -> Self {
+        Self {
+            operation,
+            mode,
+            cycles,
+            official: false,
+        }
+    }
+
+    pub const fn mnemonic(&self) -> &'static str {
+        self.operation.mnemonic()
+    }
+
+    pub const fn len(&self) -> u16 {
+        self.mode.len()
+    }
+}
+
+/// All 256 opcodes of the NMOS 6502, the unofficial mnemonics follow nestest
+pub const OPCODES: [Opcode; 256] = [
+    // $00
+    Opcode::official(Brk, Implied, 7),
+    Opcode::official(Ora, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Slo, PreindexedIndirectZeropageX, 8),
+    Opcode::unofficial(Nop, Zeropage, 3),
+    Opcode::official(Ora, Zeropage, 3),
+    Opcode::official(Asl, Zeropage, 5),
+    Opcode::unofficial(Slo, Zeropage, 5),
+    Opcode::official(Php, Implied, 3),
+    Opcode::official(Ora, Immediate, 2),
+    Opcode::official(Asl, Accumulator, 2),
+    Opcode::unofficial(Anc, Immediate, 2),
+    Opcode::unofficial(Nop, Absolute, 4),
+    Opcode::official(Ora, Absolute, 4),
+    Opcode::official(Asl, Absolute, 6),
+    Opcode::unofficial(Slo, Absolute, 6),
+    // $10
+    Opcode::official(Bpl, Relative, 2),
+    Opcode::official(Ora, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Slo, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(Ora, ZeropageX, 4),
+    Opcode::official(Asl, ZeropageX, 6),
+    Opcode::unofficial(Slo, ZeropageX, 6),
+    Opcode::official(Clc, Implied, 2),
+    Opcode::official(Ora, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Slo, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(Ora, AbsoluteX, 4),
+    Opcode::official(Asl, AbsoluteX, 7),
+    Opcode::unofficial(Slo, AbsoluteX, 7),
+    // $20
+    Opcode::official(Jsr, Absolute, 6),
+    Opcode::official(And, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Rla, PreindexedIndirectZeropageX, 8),
+    Opcode::official(Bit, Zeropage, 3),
+    Opcode::official(And, Zeropage, 3),
+    Opcode::official(Rol, Zeropage, 5),
+    Opcode::unofficial(Rla, Zeropage, 5),
+    Opcode::official(Plp, Implied, 4),
+    Opcode::official(And, Immediate, 2),
+    Opcode::official(Rol, Accumulator, 2),
+    Opcode::unofficial(Anc, Immediate, 2),
+    Opcode::official(Bit, Absolute, 4),
+    Opcode::official(And, Absolute, 4),
+    Opcode::official(Rol, Absolute, 6),
+    Opcode::unofficial(Rla, Absolute, 6),
+    // $30
+    Opcode::official(Bmi, Relative, 2),
+    Opcode::official(And, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Rla, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(And, ZeropageX, 4),
+    Opcode::official(Rol, ZeropageX, 6),
+    Opcode::unofficial(Rla, ZeropageX, 6),
+    Opcode::official(Sec, Implied, 2),
+    Opcode::official(And, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Rla, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(And, AbsoluteX, 4),
+    Opcode::official(Rol, AbsoluteX, 7),
+    Opcode::unofficial(Rla, AbsoluteX, 7),
+    // $40
+    Opcode::official(Rti, Implied, 6),
+    Opcode::official(Eor, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Sre, PreindexedIndirectZeropageX, 8),
+    Opcode::unofficial(Nop, Zeropage, 3),
+    Opcode::official(Eor, Zeropage, 3),
+    Opcode::official(Lsr, Zeropage, 5),
+    Opcode::unofficial(Sre, Zeropage, 5),
+    Opcode::official(Pha, Implied, 3),
+    Opcode::official(Eor, Immediate, 2),
+    Opcode::official(Lsr, Accumulator, 2),
+    Opcode::unofficial(Alr, Immediate, 2),
+    Opcode::official(Jmp, Absolute, 3),
+    Opcode::official(Eor, Absolute, 4),
+    Opcode::official(Lsr, Absolute, 6),
+    Opcode::unofficial(Sre, Absolute, 6),
+    // $50
+    Opcode::official(Bvc, Relative, 2),
+    Opcode::official(Eor, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Sre, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(Eor, ZeropageX, 4),
+    Opcode::official(Lsr, ZeropageX, 6),
+    Opcode::unofficial(Sre, ZeropageX, 6),
+    Opcode::official(Cli, Implied, 2),
+    Opcode::official(Eor, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Sre, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(Eor, AbsoluteX, 4),
+    Opcode::official(Lsr, AbsoluteX, 7),
+    Opcode::unofficial(Sre, AbsoluteX, 7),
+    // $60
+    Opcode::official(Rts, Implied, 6),
+    Opcode::official(Adc, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Rra, PreindexedIndirectZeropageX, 8),
+    Opcode::unofficial(Nop, Zeropage, 3),
+    Opcode::official(Adc, Zeropage, 3),
+    Opcode::official(Ror, Zeropage, 5),
+    Opcode::unofficial(Rra, Zeropage, 5),
+    Opcode::official(Pla, Implied, 4),
+    Opcode::official(Adc, Immediate, 2),
+    Opcode::official(Ror, Accumulator, 2),
+    Opcode::unofficial(Arr, Immediate, 2),
+    Opcode::official(Jmp, Indirect, 5),
+    Opcode::official(Adc, Absolute, 4),
+    Opcode::official(Ror, Absolute, 6),
+    Opcode::unofficial(Rra, Absolute, 6),
+    // $70
+    Opcode::official(Bvs, Relative, 2),
+    Opcode::official(Adc, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Rra, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(Adc, ZeropageX, 4),
+    Opcode::official(Ror, ZeropageX, 6),
+    Opcode::unofficial(Rra, ZeropageX, 6),
+    Opcode::official(Sei, Implied, 2),
+    Opcode::official(Adc, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Rra, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(Adc, AbsoluteX, 4),
+    Opcode::official(Ror, AbsoluteX, 7),
+    Opcode::unofficial(Rra, AbsoluteX, 7),
+    // $80
+    Opcode::unofficial(Nop, Immediate, 2),
+    Opcode::official(Sta, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Nop, Immediate, 2),
+    Opcode::unofficial(Sax, PreindexedIndirectZeropageX, 6),
+    Opcode::official(Sty, Zeropage, 3),
+    Opcode::official(Sta, Zeropage, 3),
+    Opcode::official(Stx, Zeropage, 3),
+    Opcode::unofficial(Sax, Zeropage, 3),
+    Opcode::official(Dey, Implied, 2),
+    Opcode::unofficial(Nop, Immediate, 2),
+    Opcode::official(Txa, Implied, 2),
+    Opcode::unofficial(Xaa, Immediate, 2),
+    Opcode::official(Sty, Absolute, 4),
+    Opcode::official(Sta, Absolute, 4),
+    Opcode::official(Stx, Absolute, 4),
+    Opcode::unofficial(Sax, Absolute, 4),
+    // $90
+    Opcode::official(Bcc, Relative, 2),
+    Opcode::official(Sta, PostindexedIndirectZeropageY, 6),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Sha, PostindexedIndirectZeropageY, 6),
+    Opcode::official(Sty, ZeropageX, 4),
+    Opcode::official(Sta, ZeropageX, 4),
+    Opcode::official(Stx, ZeropageY, 4),
+    Opcode::unofficial(Sax, ZeropageY, 4),
+    Opcode::official(Tya, Implied, 2),
+    Opcode::official(Sta, AbsoluteY, 5),
+    Opcode::official(Txs, Implied, 2),
+    Opcode::unofficial(Tas, AbsoluteY, 5),
+    Opcode::unofficial(Shy, AbsoluteX, 5),
+    Opcode::official(Sta, AbsoluteX, 5),
+    Opcode::unofficial(Shx, AbsoluteY, 5),
+    Opcode::unofficial(Sha, AbsoluteY, 5),
+    // $A0
+    Opcode::official(Ldy, Immediate, 2),
+    Opcode::official(Lda, PreindexedIndirectZeropageX, 6),
+    Opcode::official(Ldx, Immediate, 2),
+    Opcode::unofficial(Lax, PreindexedIndirectZeropageX, 6),
+    Opcode::official(Ldy, Zeropage, 3),
+    Opcode::official(Lda, Zeropage, 3),
+    Opcode::official(Ldx, Zeropage, 3),
+    Opcode::unofficial(Lax, Zeropage, 3),
+    Opcode::official(Tay, Implied, 2),
+    Opcode::official(Lda, Immediate, 2),
+    Opcode::official(Tax, Implied, 2),
+    Opcode::unofficial(Lax, Immediate, 2),
+    Opcode::official(Ldy, Absolute, 4),
+    Opcode::official(Lda, Absolute, 4),
+    Opcode::official(Ldx, Absolute, 4),
+    Opcode::unofficial(Lax, Absolute, 4),
+    // $B0
+    Opcode::official(Bcs, Relative, 2),
+    Opcode::official(Lda, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Lax, PostindexedIndirectZeropageY, 5),
+    Opcode::official(Ldy, ZeropageX, 4),
+    Opcode::official(Lda, ZeropageX, 4),
+    Opcode::official(Ldx, ZeropageY, 4),
+    Opcode::unofficial(Lax, ZeropageY, 4),
+    Opcode::official(Clv, Implied, 2),
+    Opcode::official(Lda, AbsoluteY, 4),
+    Opcode::official(Tsx, Implied, 2),
+    Opcode::unofficial(Las, AbsoluteY, 4),
+    Opcode::official(Ldy, AbsoluteX, 4),
+    Opcode::official(Lda, AbsoluteX, 4),
+    Opcode::official(Ldx, AbsoluteY, 4),
+    Opcode::unofficial(Lax, AbsoluteY, 4),
+    // $C0
+    Opcode::official(Cpy, Immediate, 2),
+    Opcode::official(Cmp, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Nop, Immediate, 2),
+    Opcode::unofficial(Dcp, PreindexedIndirectZeropageX, 8),
+    Opcode::official(Cpy, Zeropage, 3),
+    Opcode::official(Cmp, Zeropage, 3),
+    Opcode::official(Dec, Zeropage, 5),
+    Opcode::unofficial(Dcp, Zeropage, 5),
+    Opcode::official(Iny, Implied, 2),
+    Opcode::official(Cmp, Immediate, 2),
+    Opcode::official(Dex, Implied, 2),
+    Opcode::unofficial(Axs, Immediate, 2),
+    Opcode::official(Cpy, Absolute, 4),
+    Opcode::official(Cmp, Absolute, 4),
+    Opcode::official(Dec, Absolute, 6),
+    Opcode::unofficial(Dcp, Absolute, 6),
+    // $D0
+    Opcode::official(Bne, Relative, 2),
+    Opcode::official(Cmp, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Dcp, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(Cmp, ZeropageX, 4),
+    Opcode::official(Dec, ZeropageX, 6),
+    Opcode::unofficial(Dcp, ZeropageX, 6),
+    Opcode::official(Cld, Implied, 2),
+    Opcode::official(Cmp, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Dcp, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(Cmp, AbsoluteX, 4),
+    Opcode::official(Dec, AbsoluteX, 7),
+    Opcode::unofficial(Dcp, AbsoluteX, 7),
+    // $E0
+    Opcode::official(Cpx, Immediate, 2),
+    Opcode::official(Sbc, PreindexedIndirectZeropageX, 6),
+    Opcode::unofficial(Nop, Immediate, 2),
+    Opcode::unofficial(Isb, PreindexedIndirectZeropageX, 8),
+    Opcode::official(Cpx, Zeropage, 3),
+    Opcode::official(Sbc, Zeropage, 3),
+    Opcode::official(Inc, Zeropage, 5),
+    Opcode::unofficial(Isb, Zeropage, 5),
+    Opcode::official(Inx, Implied, 2),
+    Opcode::official(Sbc, Immediate, 2),
+    Opcode::official(Nop, Implied, 2),
+    Opcode::unofficial(Sbc, Immediate, 2),
+    Opcode::official(Cpx, Absolute, 4),
+    Opcode::official(Sbc, Absolute, 4),
+    Opcode::official(Inc, Absolute, 6),
+    Opcode::unofficial(Isb, Absolute, 6),
+    // $F0
+    Opcode::official(Beq, Relative, 2),
+    Opcode::official(Sbc, PostindexedIndirectZeropageY, 5),
+    Opcode::unofficial(Jam, Implied, 2),
+    Opcode::unofficial(Isb, PostindexedIndirectZeropageY, 8),
+    Opcode::unofficial(Nop, ZeropageX, 4),
+    Opcode::official(Sbc, ZeropageX, 4),
+    Opcode::official(Inc, ZeropageX, 6),
+    Opcode::unofficial(Isb, ZeropageX, 6),
+    Opcode::official(Sed, Implied, 2),
+    Opcode::official(Sbc, AbsoluteY, 4),
+    Opcode::unofficial(Nop, Implied, 2),
+    Opcode::unofficial(Isb, AbsoluteY, 7),
+    Opcode::unofficial(Nop, AbsoluteX, 4),
+    Opcode::official(Sbc, AbsoluteX, 4),
+    Opcode::official(Inc, AbsoluteX, 7),
+    Opcode::unofficial(Isb, AbsoluteX, 7),
+];
+
+/// The opcodes of the WDC 65C02, which replaces all unofficial NMOS opcodes
+/// (see http://www.6502.org/tutorials/65c02opcodes.html)
+pub const CMOS_OPCODES: [Opcode; 256] = cmos_opcodes();
+
+const fn cmos_opcodes() -> [Opcode; 256] {
+    let mut opcodes = OPCODES;
+
+    // the undefined opcodes are NOPs of various lengths
+    let mut i = 0;
+    while i < 16 {
+        opcodes[(i << 4) | 0x03] = Opcode::unofficial(NopSingleCycle, Implied, 1);
+        opcodes[(i << 4) | 0x0B] = Opcode::unofficial(NopSingleCycle, Implied, 1);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 8 {
+        let bit = i as u8;
+        opcodes[(i << 4) | 0x07] = Opcode::official(Rmb(bit), Zeropage, 5);
+        opcodes[(i << 4) | 0x87] = Opcode::official(Smb(bit), Zeropage, 5);
+        opcodes[(i << 4) | 0x0F] = Opcode::official(Bbr(bit), ZeropageRelative, 5);
+        opcodes[(i << 4) | 0x8F] = Opcode::official(Bbs(bit), ZeropageRelative, 5);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 7 {
+        opcodes[[0x02, 0x22, 0x42, 0x62, 0x82, 0xC2, 0xE2][i]] =
+            Opcode::unofficial(Nop, Immediate, 2);
+        i += 1;
+    }
+    opcodes[0x44] = Opcode::unofficial(Nop, Zeropage, 3);
+    opcodes[0x54] = Opcode::unofficial(Nop, ZeropageX, 4);
+    opcodes[0xD4] = Opcode::unofficial(Nop, ZeropageX, 4);
+    opcodes[0xF4] = Opcode::unofficial(Nop, ZeropageX, 4);
+    opcodes[0xDC] = Opcode::unofficial(Nop, Absolute, 4);
+    opcodes[0xFC] = Opcode::unofficial(Nop, Absolute, 4);
+    opcodes[0x5C] = Opcode::unofficial(NopEightCycles, Absolute, 8);
+
+    opcodes[0x12] = Opcode::official(Ora, IndirectZeropage, 5);
+    opcodes[0x32] = Opcode::official(And, IndirectZeropage, 5);
+    opcodes[0x52] = Opcode::official(Eor, IndirectZeropage, 5);
+    opcodes[0x72] = Opcode::official(Adc, IndirectZeropage, 5);
+    opcodes[0x92] = Opcode::official(Sta, IndirectZeropage, 5);
+    opcodes[0xB2] = Opcode::official(Lda, IndirectZeropage, 5);
+    opcodes[0xD2] = Opcode::official(Cmp, IndirectZeropage, 5);
+    opcodes[0xF2] = Opcode::official(Sbc, IndirectZeropage, 5);
+
+    opcodes[0x64] = Opcode::official(Stz, Zeropage, 3);
+    opcodes[0x74] = Opcode::official(Stz, ZeropageX, 4);
+    opcodes[0x9C] = Opcode::official(Stz, Absolute, 4);
+    opcodes[0x9E] = Opcode::official(Stz, AbsoluteX, 5);
+
+    opcodes[0x04] = Opcode::official(Tsb, Zeropage, 5);
+    opcodes[0x0C] = Opcode::official(Tsb, Absolute, 6);
+    opcodes[0x14] = Opcode::official(Trb, Zeropage, 5);
+    opcodes[0x1C] = Opcode::official(Trb, Absolute, 6);
+
+    opcodes[0x89] = Opcode::official(Bit, Immediate, 2);
+    opcodes[0x34] = Opcode::official(Bit, ZeropageX, 4);
+    opcodes[0x3C] = Opcode::official(Bit, AbsoluteX, 4);
+
+    opcodes[0x1A] = Opcode::official(Inc, Accumulator, 2);
+    opcodes[0x3A] = Opcode::official(Dec, Accumulator, 2);
+
+    // indexed shifts only take the extra cycle on a page crossing
+    opcodes[0x1E] = Opcode::official(Asl, AbsoluteX, 6);
+    opcodes[0x3E] = Opcode::official(Rol, AbsoluteX, 6);
+    opcodes[0x5E] = Opcode::official(Lsr, AbsoluteX, 6);
+    opcodes[0x7E] = Opcode::official(Ror, AbsoluteX, 6);
+    // the high byte of the pointer is read from the next page
+    opcodes[0x6C] = Opcode::official(Jmp, Indirect, 6);
+
+    opcodes[0xDA] = Opcode::official(Phx, Implied, 3);
+    opcodes[0x5A] = Opcode::official(Phy, Implied, 3);
+    opcodes[0xFA] = Opcode::official(Plx, Implied, 4);
+    opcodes[0x7A] = Opcode::official(Ply, Implied, 4);
+
+    opcodes[0x80] = Opcode::official(Bra, Relative, 3);
+    opcodes[0x7C] = Opcode::official(Jmp, AbsoluteXIndirect, 6);
+
+    opcodes[0xCB] = Opcode::official(Wai, Implied, 3);
+    opcodes[0xDB] = Opcode::official(Stp, Implied, 3);
+
+    opcodes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::opcodes::{Operation, CMOS_OPCODES, OPCODES};
+
+    #[test]
+    fn opcode_table() {
+        assert_eq!(OPCODES.iter().filter(|opcode| opcode.official).count(), 151);
+        assert_eq!(OPCODES[0xEA].mnemonic(), "NOP");
+        assert!(OPCODES[0xEA].official);
+        assert!(!OPCODES[0xEB].official);
+        assert_eq!(OPCODES[0x6C].len(), 3);
+        assert_eq!(OPCODES[0x91].cycles, 6);
+    }
+
+    #[test]
+    fn cmos_opcode_table() {
+        assert_eq!(
+            CMOS_OPCODES.iter().filter(|opcode| opcode.official).count(),
+            212
+        );
+        assert_eq!(CMOS_OPCODES[0xD7].operation, Operation::Smb(5));
+        assert_eq!(CMOS_OPCODES[0xD7].mnemonic(), "SMB5");
+        assert_eq!(CMOS_OPCODES[0x1F].mnemonic(), "BBR1");
+        assert_eq!(CMOS_OPCODES[0x1F].len(), 3);
+        assert!(CMOS_OPCODES
+            .iter()
+            .all(|opcode| opcode.official || opcode.mnemonic() == "NOP"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InstructionStats {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// Cycles from the call until the return, including everything that was called from it
+    pub inclusive_cycles: u64,
+    /// Cycles spent in the subroutine itself
+    pub exclusive_cycles: u64,
+}
+
+/// Statistics collected by the profiler, subroutines are keyed by their entry address and
+/// interrupt handlers are counted as subroutines
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub instructions: HashMap<u16, InstructionStats>,
+    pub subroutines: HashMap<u16, SubroutineStats>,
+    pub cycles: u64,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total cycles: {}", self.cycles)?;
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines
+            .sort_by_key(|(addr, stats)| (std::cmp::Reverse(stats.exclusive_cycles), **addr));
+        writeln!(f, "\nSubroutine       calls   inclusive   exclusive")?;
+        for (addr, stats) in subroutines {
+            writeln!(
+                f,
+                "${:04X}      {:>10}  {:>10}  {:>10}",
+                addr, stats.calls, stats.inclusive_cycles, stats.exclusive_cycles
+            )?;
+        }
+
+        let mut instructions: Vec<_> = self.instructions.iter().collect();
+        instructions.sort_by_key(|(addr, stats)| (std::cmp::Reverse(stats.cycles), **addr));
+        writeln!(f, "\nPC               count      cycles")?;
+        for (addr, stats) in instructions {
+            writeln!(
+                f,
+                "${:04X}      {:>10}  {:>10}",
+                addr, stats.count, stats.cycles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct CallFrame {
+    addr: u16,
+    /// The stack pointer before the return address was pushed
+    stack: u8,
+    start_cycle: u64,
+    child_cycles: u64,
+}
+
+/// Counts executed instructions and cycles per address and attributes cycles to JSR/RTS and
+/// interrupt/RTI call frames
+///
+/// Subroutines that never return (like a main loop) only show up in the per address statistics.
+#[derive(Default)]
+pub struct Profiler {
+    profile: Profile,
+    calls: Vec<CallFrame>,
+    /// Receives a report at the end of every frame, the profile is restarted afterwards
+    frame_sink: Option<Box<dyn Write>>,
+    frame: u64,
+}
+
+impl Profiler {
+    /// Aggregates the statistics until the profiler is removed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes a separate report for every frame
+    pub fn per_frame(sink: impl Write + 'static) -> Self {
+        Self {
+            frame_sink: Some(Box::new(sink)),
+            ..Self::default()
+        }
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub(super) fn record_instruction(&mut self, pc: u16, cycles: u64) {
+        let stats = self.profile.instructions.entry(pc).or_default();
+        stats.count += 1;
+        stats.cycles += cycles;
+        self.profile.cycles += cycles;
+    }
+
+    pub(super) fn enter(&mut self, addr: u16, stack: u8, cycle: u64) {
+        self.calls.push(CallFrame {
+            addr,
+            stack,
+            start_cycle: cycle,
+            child_cycles: 0,
+        });
+    }
+
+    /// Closes the frame the stack pointer returned to, including frames that were left by
+    /// manipulating the stack
+    pub(super) fn leave(&mut self, stack: u8, cycle: u64) {
+        // an RTS used as an indirect jump returns into the current frame
+        while self.calls.last().is_some_and(|frame| frame.stack <= stack) {
+            let frame = self.calls.pop().unwrap();
+            let inclusive = cycle - frame.start_cycle;
+            let stats = self.profile.subroutines.entry(frame.addr).or_default();
+            stats.calls += 1;
+            stats.inclusive_cycles += inclusive;
+            stats.exclusive_cycles += inclusive.saturating_sub(frame.child_cycles);
+            if let Some(parent) = self.calls.last_mut() {
+                parent.child_cycles += inclusive;
+            }
+        }
+    }
+
+    pub(super) fn end_frame(&mut self) {
+        let Some(sink) = self.frame_sink.as_mut() else {
+            return;
+        };
+        if let Err(e) = writeln!(sink, "Frame {}\n{}", self.frame, self.profile) {
+            println!(
+                "Failed to write profile, only aggregating from now on: {}",
+                e
+            );
+            self.frame_sink = None;
+            return;
+        }
+        self.frame += 1;
+        self.profile = Profile::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::profiler::{InstructionStats, Profiler, SubroutineStats};
+    use crate::cpu::{Cpu, CpuOptions};
+    use crate::memory::{Memory, Ram};
+
+    #[test]
+    fn subroutine_cycles() {
+        let mut memory = Ram::new(0x10000);
+        // JSR outer, NOP; outer: JSR inner, RTS; inner: NOP, RTS
+        let program = [
+            (0x0400, vec![0x20, 0x00, 0x05, 0xEA]),
+            (0x0500, vec![0x20, 0x00, 0x06, 0x60]),
+            (0x0600, vec![0xEA, 0x60]),
+        ];
+        for (addr, bytes) in program {
+            for (i, byte) in bytes.into_iter().enumerate() {
+                memory.write(addr + i as u16, byte);
+            }
+        }
+
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.registers.pc = 0x0400;
+        cpu.registers.s = 0xFF;
+        cpu.set_profiler(Some(Profiler::new()));
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 0x0404);
+
+        let profile = cpu.take_profiler().unwrap().profile().clone();
+        assert_eq!(profile.cycles, 6 + 6 + 2 + 6 + 6 + 2);
+        assert_eq!(
+            profile.instructions[&0x0400],
+            InstructionStats {
+                count: 1,
+                cycles: 6
+            }
+        );
+        assert_eq!(
+            profile.subroutines[&0x0600],
+            SubroutineStats {
+                calls: 1,
+                inclusive_cycles: 2 + 6,
+                exclusive_cycles: 2 + 6,
+            }
+        );
+        assert_eq!(
+            profile.subroutines[&0x0500],
+            SubroutineStats {
+                calls: 1,
+                inclusive_cycles: 6 + 8 + 6,
+                exclusive_cycles: 6 + 6,
+            }
+        );
+        assert!(profile
+            .to_string()
+            .contains("$0500               1          20          12"));
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Writes a nestest/Nintendulator style line for every executed instruction
+pub struct Tracer {
+    sink: Box<dyn Write>,
+    enabled: bool,
+    /// Only instructions in this range are logged
+    pub pc_range: Option<RangeInclusive<u16>>,
+}
+
+impl Tracer {
+    pub fn new(sink: impl Write + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            enabled: true,
+            pc_range: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Disabling the trace flushes it, so the log ends with the last traced instruction
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.sink.flush() {
+            println!("Failed to flush trace: {}", e);
+        }
+    }
+
+    pub fn should_trace(&self, pc: u16) -> bool {
+        self.enabled
+            && self
+                .pc_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&pc))
+    }
+
+    /// Writes a line, tracing is disabled when the sink fails
+    pub fn trace(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.sink, "{}", line) {
+            println!("Failed to write trace, disabling it: {}", e);
+            self.enabled = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::trace::Tracer;
+    use crate::cpu::{Cpu, CpuOptions};
+    use crate::memory::{Memory, Ram};
+    use std::cell::RefCell;
+    use std::io::{BufWriter, Write};
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            let log = String::from_utf8(self.0.borrow().clone()).unwrap();
+            log.lines().map(String::from).collect()
+        }
+    }
+
+    #[test]
+    fn pc_range() {
+        let mut memory = Ram::new(0x10000);
+        // LDX #$02, loop: DEX, BNE loop, NOP
+        for (i, opcode) in [0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0xEA].into_iter().enumerate() {
+            memory.write(0x0400 + i as u16, opcode);
+        }
+
+        let buffer = SharedBuffer::default();
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        let mut tracer = Tracer::new(buffer.clone());
+        tracer.pc_range = Some(0x0402..=0x0403);
+        cpu.set_tracer(Some(tracer));
+        cpu.registers.pc = 0x0400;
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "0402  CA        DEX                             A:00 X:02 Y:00 P:00 SP:00 CYC:2"
+        );
+        assert!(lines[1].starts_with("0403  D0 FD     BNE $0402  "));
+
+        let len = buffer.0.borrow().len();
+        cpu.tracer_mut().unwrap().set_enabled(false);
+        cpu.registers.pc = 0x0402;
+        cpu.step().unwrap();
+        assert_eq!(buffer.0.borrow().len(), len);
+    }
+
+    #[test]
+    fn disabling_flushes() {
+        let mut memory = Ram::new(0x10000);
+        // NOP, NOP
+        memory.write(0x0400, 0xEA);
+        memory.write(0x0401, 0xEA);
+
+        let buffer = SharedBuffer::default();
+        let mut cpu = Cpu::new(memory, CpuOptions::default());
+        cpu.set_tracer(Some(Tracer::new(BufWriter::new(buffer.clone()))));
+        cpu.registers.pc = 0x0400;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(buffer.lines().is_empty());
+
+        cpu.tracer_mut().unwrap().set_enabled(false);
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("0401  EA        NOP"));
+    }
+}
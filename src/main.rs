@@ -1,54 +1,238 @@
 mod cpu;
+mod mapper;
 mod memory;
 mod nes_rom;
+mod pacing;
 mod ppu;
 
+use crate::cpu::cdl::CodeDataLogger;
 use crate::cpu::controller::{
     CONTROLLER_BUTTON_A, CONTROLLER_BUTTON_B, CONTROLLER_BUTTON_DOWN, CONTROLLER_BUTTON_LEFT,
     CONTROLLER_BUTTON_RIGHT, CONTROLLER_BUTTON_SELECT, CONTROLLER_BUTTON_START,
     CONTROLLER_BUTTON_UP,
 };
+use crate::cpu::gdb::GdbStub;
+use crate::cpu::monitor::Monitor;
+use crate::cpu::profiler::Profiler;
+use crate::cpu::trace::Tracer;
+use crate::memory::RamFill;
 use crate::nes_rom::NesRom;
+use crate::pacing::Pacer;
 use crate::render::{debug_chr_rom, render_frame};
 use cpu::bus::Bus;
 use cpu::Cpu;
 use macroquad::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 mod render;
 
-#[macroquad::main("emurs")]
-async fn main() -> Result<(), anyhow::Error> {
+const TRACE_LOG_PATH: &str = "trace.log";
+const PROFILE_PATH: &str = "profile.txt";
+
+// the window is only opened after the arguments are handled, `--headless` runs without a display
+fn main() -> Result<(), anyhow::Error> {
     println!("Starting Emulator!");
 
     // let rom = NesRom::read_from_file("vendor/nes-test-roms/blargg_litewall/litewall5.nes")?;
     let rom = NesRom::read_from_file("./lode_runner.nes")?;
     println!("{rom:#?}");
 
-    let bus = Bus::new(rom.clone());
+    let args: Vec<String> = std::env::args().collect();
+
+    // `--ram-fill zeros|ff|random[:seed]` sets the contents of RAM at power-on
+    let ram_fill = match args.iter().position(|arg| arg == "--ram-fill") {
+        Some(idx) => parse_ram_fill(args.get(idx + 1).map_or("", String::as_str))?,
+        None => RamFill::default(),
+    };
+
+    let mut bus = Bus::new(rom.clone());
+    bus.ram_fill = ram_fill;
+
+    // `--cdl <path>` logs code and data accesses, continuing an existing log, F7 saves it
+    let cdl_path = match args.iter().position(|arg| arg == "--cdl") {
+        Some(idx) => Some(
+            args.get(idx + 1)
+                .ok_or_else(|| anyhow::anyhow!("--cdl needs a path"))?
+                .clone(),
+        ),
+        None => None,
+    };
+    if let Some(path) = cdl_path.as_ref() {
+        let (prg_size, chr_size) = (rom.prg_rom.len(), rom.chr_rom.len());
+        bus.cdl = Some(match File::open(path) {
+            Ok(file) => CodeDataLogger::load(file, prg_size, chr_size)?,
+            Err(_) => CodeDataLogger::new(prg_size, chr_size),
+        });
+    }
     println!("Entry point: {:#X}", bus.reset_vector());
 
-    let mut cpu = Cpu::with_nes_options(bus, 1 << 31);
-    cpu.reset();
+    let mut cpu = Cpu::with_nes_options(bus);
+    cpu.power_on();
+
+    // `--block-cache` executes instructions from decoded basic blocks instead of fetching them
+    if args.iter().any(|arg| arg == "--block-cache") {
+        cpu.set_block_cache(true);
+    }
+
+    // `--headless <frames>` runs that many frames as fast as possible without rendering them
+    if let Some(idx) = args.iter().position(|arg| arg == "--headless") {
+        let frames = args
+            .get(idx + 1)
+            .ok_or_else(|| anyhow::anyhow!("--headless needs a number of frames"))?
+            .parse()?;
+        return run_headless(&mut cpu, frames);
+    }
+
+    let pacer = Pacer::new(&rom.tv_system);
+
+    // `--gdb [port]` starts a GDB remote serial protocol server on localhost, port 6502 by default
+    let gdb = match args.iter().position(|arg| arg == "--gdb") {
+        Some(idx) => {
+            let port = args.get(idx + 1).map_or("6502", String::as_str);
+            let stub = GdbStub::bind(format!("127.0.0.1:{}", port))?;
+            println!("Waiting for GDB on {}", stub.local_addr()?);
+            Some(stub)
+        }
+        None => None,
+    };
+
+    // `--monitor` reads debugger commands from stdin
+    let monitor = args
+        .iter()
+        .any(|arg| arg == "--monitor")
+        .then(Monitor::spawn);
+
+    // `--profile-frames` makes the profiler write a report for every frame instead of one in total
+    let profile_frames = args.iter().any(|arg| arg == "--profile-frames");
+
+    macroquad::Window::new(
+        "emurs",
+        run(cpu, pacer, gdb, monitor, profile_frames, cdl_path),
+    );
+    Ok(())
+}
 
+async fn run(
+    mut cpu: Cpu<Bus>,
+    mut pacer: Pacer,
+    mut gdb: Option<GdbStub>,
+    mut monitor: Option<Monitor>,
+    profile_frames: bool,
+    cdl_path: Option<String>,
+) {
+    // closing the window ends the loop instead of the process, so the trace can be flushed
+    prevent_quit();
     let mut show_chr_rom_debug = false;
-    loop {
+    while !is_quit_requested() {
         const TOGGLE_CHR_DEBUG_KEY: KeyCode = KeyCode::C;
         if is_key_pressed(TOGGLE_CHR_DEBUG_KEY) {
             show_chr_rom_debug = !show_chr_rom_debug;
         }
         if show_chr_rom_debug {
-            debug_chr_rom(&rom).await;
+            debug_chr_rom(&cpu.bus.ppu.memory).await;
         } else {
             if cpu.poll_new_frame() {
                 render_frame(&mut cpu).await;
+                handle_pacing_input(&mut pacer);
+                handle_reset_input(&mut cpu);
+                handle_trace_input(&mut cpu);
+                handle_profiler_input(&mut cpu, profile_frames);
+                handle_cdl_input(&cpu, cdl_path.as_deref());
+                if let Some(gdb) = gdb.as_mut() {
+                    gdb.poll(&mut cpu);
+                }
+                if let Some(monitor) = monitor.as_mut() {
+                    monitor.poll(&mut cpu);
+                }
+                pacer.end_frame();
             }
-            cpu.tick();
+
+            let paused = cpu.break_hit().is_some() || cpu.halted().is_some();
+            if let Some(gdb) = gdb.as_mut().filter(|gdb| paused || gdb.is_halted()) {
+                wait_for_debugger(gdb, &mut cpu).await;
+            } else if let Some(monitor) = monitor.as_mut().filter(|m| paused || m.is_paused()) {
+                wait_for_monitor(monitor, &mut cpu).await;
+            } else if let Some(error) = cpu.halted() {
+                println!("{}", error);
+                wait_for_reset(&mut cpu).await;
+            } else if let Some(hit) = cpu.break_hit() {
+                println!("{}", hit);
+                wait_for_resume(&mut cpu).await;
+            }
+            // errors halt the CPU and are handled above
+            let _ = cpu.tick();
             handle_keyboard_input(&mut cpu);
         }
     }
+
+    if let Some(tracer) = cpu.tracer_mut() {
+        tracer.flush();
+    }
+}
+
+fn run_headless(cpu: &mut Cpu<Bus>, frames: u32) -> Result<(), anyhow::Error> {
+    let start = Instant::now();
+    let mut frame = 0;
+    while frame < frames {
+        if let Err(error) = cpu.tick() {
+            anyhow::bail!("{} in frame {}", error, frame);
+        }
+        if cpu.poll_new_frame() {
+            frame += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} frames in {:.3} s ({:.1} fps)",
+        frames,
+        elapsed.as_secs_f64(),
+        f64::from(frames) / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Keeps the window responsive while the CPU is paused on a breakpoint
+async fn wait_for_resume(cpu: &mut Cpu<Bus>) {
+    println!("Paused, press F5 to continue");
+    while !is_key_pressed(KeyCode::F5) && !is_quit_requested() {
+        next_frame().await;
+    }
+    cpu.resume();
 }
 
-fn handle_keyboard_input(cpu: &mut Cpu) {
+/// Keeps the window responsive while the CPU is halted by an error
+async fn wait_for_reset(cpu: &mut Cpu<Bus>) {
+    println!("Press R to reset");
+    while !is_key_pressed(KeyCode::R) {
+        if is_quit_requested() {
+            return;
+        }
+        next_frame().await;
+    }
+    cpu.reset();
+}
+
+/// Keeps the window responsive while GDB holds the CPU
+async fn wait_for_debugger(gdb: &mut GdbStub, cpu: &mut Cpu<Bus>) {
+    gdb.poll(cpu);
+    while gdb.is_halted() && !is_quit_requested() {
+        next_frame().await;
+        gdb.poll(cpu);
+    }
+}
+
+/// Keeps the window responsive while the emulation is paused from the monitor
+async fn wait_for_monitor(monitor: &mut Monitor, cpu: &mut Cpu<Bus>) {
+    monitor.poll(cpu);
+    while monitor.is_paused() && !is_quit_requested() {
+        next_frame().await;
+        monitor.poll(cpu);
+    }
+}
+
+fn handle_keyboard_input(cpu: &mut Cpu<Bus>) {
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_A] = is_key_down(KeyCode::S);
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_B] = is_key_down(KeyCode::A);
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_SELECT] = is_key_down(KeyCode::LeftShift);
@@ -57,8 +241,90 @@ fn handle_keyboard_input(cpu: &mut Cpu) {
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_DOWN] = is_key_down(KeyCode::Down);
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_LEFT] = is_key_down(KeyCode::Left);
     cpu.bus.controller.button_states[CONTROLLER_BUTTON_RIGHT] = is_key_down(KeyCode::Right);
+}
 
-    if is_key_down(KeyCode::R) {
+fn handle_reset_input(cpu: &mut Cpu<Bus>) {
+    if is_key_pressed(KeyCode::R) {
         cpu.reset();
     }
+    if is_key_pressed(KeyCode::P) {
+        cpu.power_on();
+    }
+}
+
+// key presses are only registered once per rendered frame
+fn handle_trace_input(cpu: &mut Cpu<Bus>) {
+    if is_key_pressed(KeyCode::T) {
+        match cpu.tracer_mut() {
+            Some(tracer) => tracer.set_enabled(!tracer.is_enabled()),
+            None => match File::create(TRACE_LOG_PATH) {
+                Ok(file) => cpu.set_tracer(Some(Tracer::new(BufWriter::new(file)))),
+                Err(e) => println!("Failed to create {}: {}", TRACE_LOG_PATH, e),
+            },
+        }
+    }
+}
+
+// F6 starts profiling and writes the report when pressed again
+fn handle_profiler_input(cpu: &mut Cpu<Bus>, per_frame: bool) {
+    if !is_key_pressed(KeyCode::F6) {
+        return;
+    }
+    match cpu.take_profiler() {
+        Some(profiler) if !per_frame => {
+            let result = File::create(PROFILE_PATH)
+                .and_then(|mut file| write!(file, "{}", profiler.profile()));
+            match result {
+                Ok(()) => println!("Profile written to {}", PROFILE_PATH),
+                Err(e) => println!("Failed to write {}: {}", PROFILE_PATH, e),
+            }
+        }
+        Some(_) => println!("Profiling stopped"),
+        None if per_frame => match File::create(PROFILE_PATH) {
+            Ok(file) => cpu.set_profiler(Some(Profiler::per_frame(BufWriter::new(file)))),
+            Err(e) => println!("Failed to create {}: {}", PROFILE_PATH, e),
+        },
+        None => cpu.set_profiler(Some(Profiler::new())),
+    }
+}
+
+fn handle_cdl_input(cpu: &Cpu<Bus>, path: Option<&str>) {
+    let (Some(cdl), Some(path)) = (cpu.bus.cdl.as_ref(), path) else {
+        return;
+    };
+    if is_key_pressed(KeyCode::F7) {
+        match File::create(path).and_then(|file| cdl.save(file)) {
+            Ok(()) => println!("Code/data log written to {}", path),
+            Err(e) => println!("Failed to write {}: {}", path, e),
+        }
+    }
+}
+
+fn parse_ram_fill(value: &str) -> Result<RamFill, anyhow::Error> {
+    match value.split_once(':') {
+        None if value == "zeros" => Ok(RamFill::Zeros),
+        None if value == "ff" => Ok(RamFill::Ones),
+        None if value == "random" => {
+            let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+            println!("RAM seed: {}", seed);
+            Ok(RamFill::Random(seed))
+        }
+        Some(("random", seed)) => Ok(RamFill::Random(seed.parse()?)),
+        _ => anyhow::bail!(
+            "Invalid --ram-fill {:?}, expected zeros, ff or random[:seed]",
+            value
+        ),
+    }
+}
+
+fn handle_pacing_input(pacer: &mut Pacer) {
+    if is_key_pressed(KeyCode::Tab) {
+        pacer.set_throttled(!pacer.is_throttled());
+    }
+    if is_key_pressed(KeyCode::Equal) {
+        pacer.set_speed(pacer.speed() * 2.0);
+    }
+    if is_key_pressed(KeyCode::Minus) {
+        pacer.set_speed(pacer.speed() / 2.0);
+    }
 }
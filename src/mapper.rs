@@ -0,0 +1,158 @@
+use crate::memory::RamFill;
+use crate::nes_rom::{NametableMirroring, NesRom};
+use discrete::{Board, Discrete};
+use fme7::Fme7;
+use mmc1::Mmc1;
+use mmc2::{Chip, Mmc2};
+use mmc3::Mmc3;
+use nrom::Nrom;
+use vrc::{Vrc4, Wiring};
+use vrc6::Vrc6;
+
+pub mod discrete;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod nrom;
+pub mod vrc;
+pub mod vrc6;
+
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+/// The cartridge hardware between the console and the ROM chips, selected by the mapper number
+/// of the iNES header
+///
+/// The CPU sees the cartridge at $6000-$FFFF, the PPU at $0000-$1FFF. Registers of the mapper are
+/// written through the CPU and switch the banks of PRG and CHR memory that are visible in these
+/// ranges, enable PRG RAM and select the nametable mirroring.
+pub trait Mapper {
+    /// Reads from $6000-$FFFF without side effects
+    fn read_prg(&self, addr: u16) -> u8;
+    /// Writes to $6000-$FFFF, writes to ROM set the registers of most mappers
+    fn write_prg(&mut self, addr: u16, data: u8);
+    /// Reads from the pattern tables at $0000-$1FFF
+    fn read_chr(&self, addr: u16) -> u8;
+    fn write_chr(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> NametableMirroring;
+
+    /// The offset into PRG ROM that is mapped at `addr`, `None` if it is not ROM
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize>;
+    /// The offset into CHR ROM that is mapped at `addr`, `None` for CHR RAM
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize>;
+
+    /// Advances the mapper by one CPU cycle
+    fn tick(&mut self) {}
+
+    /// Watches the address bus of the PPU, see `PpuBus::set_address`
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    /// Whether the mapper asserts the IRQ line of the CPU
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// Creates the mapper that the header of `rom` asks for, unsupported mappers are replaced with
+/// NROM so at least the first banks are mapped
+pub fn new(rom: &NesRom, ram_fill: RamFill) -> Box<dyn Mapper> {
+    let (prg_rom, chr_rom) = (rom.prg_rom.clone(), rom.chr_rom.clone());
+    let mirroring = rom.nametable_mirroring;
+    let discrete = |board: Board, prg_rom, chr_rom| -> Box<dyn Mapper> {
+        let bus_conflicts = board.has_bus_conflicts();
+        Box::new(Discrete::new(
+            board,
+            prg_rom,
+            chr_rom,
+            mirroring,
+            ram_fill,
+            bus_conflicts,
+        ))
+    };
+    let vrc4 = |wiring: Wiring, prg_rom, chr_rom| -> Box<dyn Mapper> {
+        Box::new(Vrc4::new(wiring, prg_rom, chr_rom, mirroring, ram_fill))
+    };
+    match rom.mapper() {
+        0 => Box::new(Nrom::new(prg_rom, chr_rom, mirroring, ram_fill)),
+        1 => Box::new(Mmc1::new(prg_rom, chr_rom, ram_fill)),
+        2 => discrete(Board::Uxrom, prg_rom, chr_rom),
+        3 => discrete(Board::Cnrom, prg_rom, chr_rom),
+        4 => Box::new(Mmc3::new(prg_rom, chr_rom, mirroring, ram_fill)),
+        7 => discrete(Board::Axrom, prg_rom, chr_rom),
+        9 => Box::new(Mmc2::new(Chip::Mmc2, prg_rom, chr_rom, mirroring, ram_fill)),
+        10 => Box::new(Mmc2::new(Chip::Mmc4, prg_rom, chr_rom, mirroring, ram_fill)),
+        11 => discrete(Board::ColorDreams, prg_rom, chr_rom),
+        21 => vrc4(Wiring::Vrc4AC, prg_rom, chr_rom),
+        22 => vrc4(Wiring::Vrc2A, prg_rom, chr_rom),
+        23 => vrc4(Wiring::Vrc4EF, prg_rom, chr_rom),
+        24 => Box::new(Vrc6::new(Wiring::Vrc6A, prg_rom, chr_rom, ram_fill)),
+        25 => vrc4(Wiring::Vrc4BD, prg_rom, chr_rom),
+        26 => Box::new(Vrc6::new(Wiring::Vrc6B, prg_rom, chr_rom, ram_fill)),
+        // NINA-001 has CHR ROM, BNROM CHR RAM
+        34 if chr_rom.len() > 0x2000 => discrete(Board::Nina001, prg_rom, chr_rom),
+        34 => discrete(Board::Bnrom, prg_rom, chr_rom),
+        66 => discrete(Board::Gxrom, prg_rom, chr_rom),
+        69 => Box::new(Fme7::new(prg_rom, chr_rom, ram_fill)),
+        mapper => {
+            println!("Unsupported mapper {}, falling back to NROM", mapper);
+            Box::new(Nrom::new(prg_rom, chr_rom, mirroring, ram_fill))
+        }
+    }
+}
+
+/// CHR ROM, or 8 KiB of CHR RAM for cartridges that don't have any ROM
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(chr_rom: Vec<u8>) -> Self {
+        if chr_rom.is_empty() {
+            Self {
+                data: vec![0; CHR_RAM_SIZE],
+                writable: true,
+            }
+        } else {
+            Self {
+                data: chr_rom,
+                writable: false,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = data;
+        }
+    }
+
+    pub fn rom_offset(&self, offset: usize) -> Option<usize> {
+        (!self.writable).then(|| offset % self.data.len())
+    }
+}
+
+/// The offset of byte `addr` in a window of `size` bytes that shows `bank` of `len` bytes of
+/// memory, banks past the end wrap around like they do with unconnected address lines
+pub fn bank_offset(bank: usize, size: usize, addr: u16, len: usize) -> usize {
+    (bank * size + (addr as usize & (size - 1))) % len
+}
+
+/// PRG ROM of `prg_banks` 8 KiB banks and CHR ROM of `chr_banks` 1 KiB banks for the tests of
+/// the mappers, every byte holds the number of its bank
+#[cfg(test)]
+pub fn banked_rom(prg_banks: usize, chr_banks: usize) -> (Vec<u8>, Vec<u8>) {
+    let prg_rom = (0..prg_banks).flat_map(|bank| vec![bank as u8; 0x2000]);
+    let chr_rom = (0..chr_banks).flat_map(|bank| vec![bank as u8; 0x0400]);
+    (prg_rom.collect(), chr_rom.collect())
+}
@@ -0,0 +1,292 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+/// Boards whose single bank register is built from discrete logic chips
+///
+/// Except for NINA-001 the register is written through ROM at $8000-$FFFF. Both the CPU and the
+/// ROM drive the data bus during these writes on some boards, so the register receives the value
+/// ANDed with the ROM byte at the address. Games avoid these bus conflicts by writing to a byte
+/// that holds the same value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Board {
+    /// Mapper 2: the 16 KiB bank at $8000 (bits 0-3), the last bank is fixed at $C000
+    Uxrom,
+    /// Mapper 3: the 8 KiB CHR bank (bits 0-1)
+    Cnrom,
+    /// Mapper 7: the 32 KiB bank (bits 0-2) and the VRAM page of single screen mirroring (bit 4)
+    Axrom,
+    /// Mapper 11: the 32 KiB bank (bits 0-1) and the 8 KiB CHR bank (bits 4-7)
+    ColorDreams,
+    /// Mapper 34 with CHR RAM: the 32 KiB bank (bits 0-1)
+    Bnrom,
+    /// Mapper 34 with CHR ROM: registers at $7FFD (32 KiB bank), $7FFE and $7FFF (4 KiB CHR
+    /// banks at $0000 and $1000), which are also written to PRG RAM
+    Nina001,
+    /// Mapper 66: the 32 KiB bank (bits 4-5) and the 8 KiB CHR bank (bits 0-1)
+    Gxrom,
+}
+
+impl Board {
+    /// Whether all known variants of the board have bus conflicts, UxROM and AxROM boards exist
+    /// with and without them
+    pub fn has_bus_conflicts(self) -> bool {
+        matches!(
+            self,
+            Board::Cnrom | Board::ColorDreams | Board::Bnrom | Board::Gxrom
+        )
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    /// Only NINA-001 has PRG RAM
+    prg_ram: Option<Ram>,
+    chr: Chr,
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+    /// Hardwired on all boards except AxROM
+    mirroring: NametableMirroring,
+    bus_conflicts: bool,
+}
+
+impl Discrete {
+    pub fn new(
+        board: Board,
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: NametableMirroring,
+        ram_fill: RamFill,
+        bus_conflicts: bool,
+    ) -> Self {
+        let prg_ram = (board == Board::Nina001).then(|| Ram::with_fill(PRG_RAM_SIZE, ram_fill));
+        let mirroring = match board {
+            Board::Axrom => NametableMirroring::SingleScreenLower,
+            _ => mirroring,
+        };
+        Self {
+            board,
+            prg_rom,
+            prg_ram,
+            chr: Chr::new(chr_rom),
+            prg_bank: 0,
+            chr_banks: [0, 1],
+            mirroring,
+            bus_conflicts,
+        }
+    }
+
+    fn write_register(&mut self, data: u8) {
+        match self.board {
+            Board::Uxrom => self.prg_bank = data & 0x0F,
+            Board::Cnrom => self.chr_banks[0] = data & 0x03,
+            Board::Axrom => {
+                self.prg_bank = data & 0x07;
+                self.mirroring = if data & 0x10 == 0 {
+                    NametableMirroring::SingleScreenLower
+                } else {
+                    NametableMirroring::SingleScreenUpper
+                };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = data & 0x03;
+                self.chr_banks[0] = data >> 4;
+            }
+            Board::Bnrom => self.prg_bank = data & 0x03,
+            // written through PRG RAM
+            Board::Nina001 => {}
+            Board::Gxrom => {
+                self.prg_bank = (data >> 4) & 0x03;
+                self.chr_banks[0] = data & 0x03;
+            }
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        match self.board {
+            Board::Nina001 => {
+                let bank = self.chr_banks[(addr >> 12) as usize & 1];
+                bank_offset(bank as usize, 0x1000, addr, self.chr.len())
+            }
+            _ => bank_offset(self.chr_banks[0] as usize, 0x2000, addr, self.chr.len()),
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match (self.prg_rom_offset(addr), self.prg_ram.as_ref()) {
+            (Some(offset), _) => self.prg_rom[offset],
+            (None, Some(prg_ram)) => prg_ram.read(addr - 0x6000),
+            // open bus
+            (None, None) => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if let Some(prg_ram) = self.prg_ram.as_mut() {
+                prg_ram.write(addr - 0x6000, data);
+                match addr {
+                    0x7FFD => self.prg_bank = data & 0x01,
+                    0x7FFE => self.chr_banks[0] = data & 0x0F,
+                    0x7FFF => self.chr_banks[1] = data & 0x0F,
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        let data = if self.bus_conflicts {
+            data & self.read_prg(addr)
+        } else {
+            data
+        };
+        self.write_register(data);
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let len = self.prg_rom.len();
+        Some(match (self.board, addr) {
+            (Board::Uxrom, 0x8000..=0xBFFF) => {
+                bank_offset(self.prg_bank as usize, 0x4000, addr, len)
+            }
+            (Board::Uxrom, _) => bank_offset(len / 0x4000 - 1, 0x4000, addr, len),
+            _ => bank_offset(self.prg_bank as usize, 0x8000, addr, len),
+        })
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::discrete::{Board, Discrete};
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+
+    /// `prg_banks` and `chr_banks` count 8 KiB and 1 KiB banks: 16 KiB PRG bank n holds the
+    /// 8 KiB banks 2n and 2n + 1, 4 KiB CHR bank n starts with the 1 KiB bank 4n
+    fn new_board(
+        board: Board,
+        prg_banks: usize,
+        chr_banks: usize,
+        bus_conflicts: bool,
+    ) -> Discrete {
+        let (prg_rom, chr_rom) = banked_rom(prg_banks, chr_banks);
+        Discrete::new(
+            board,
+            prg_rom,
+            chr_rom,
+            NametableMirroring::Vertical,
+            RamFill::Zeros,
+            bus_conflicts,
+        )
+    }
+
+    #[test]
+    fn uxrom() {
+        let mut uxrom = new_board(Board::Uxrom, 16, 0, false);
+        assert_eq!(uxrom.read_prg(0x8000), 0);
+        assert_eq!(uxrom.read_prg(0xC000), 14);
+        uxrom.write_prg(0x8000, 5);
+        assert_eq!(uxrom.read_prg(0xBFFF), 11);
+        assert_eq!(uxrom.read_prg(0xFFFF), 15);
+        assert_eq!(uxrom.prg_rom_offset(0x8010), Some(5 * 0x4000 + 0x10));
+
+        // CHR RAM
+        uxrom.write_chr(0x1234, 0x56);
+        assert_eq!(uxrom.read_chr(0x1234), 0x56);
+        assert_eq!(uxrom.mirroring(), NametableMirroring::Vertical);
+    }
+
+    #[test]
+    fn cnrom() {
+        let mut cnrom = new_board(Board::Cnrom, 4, 32, false);
+        cnrom.write_prg(0x8000, 2);
+        assert_eq!(cnrom.read_chr(0x0000), 16);
+        assert_eq!(cnrom.read_chr(0x1FFF), 23);
+        assert_eq!(cnrom.chr_rom_offset(0x0010), Some(4 * 0x1000 + 0x10));
+        assert_eq!(cnrom.read_prg(0xC000), 2);
+    }
+
+    #[test]
+    fn axrom() {
+        let mut axrom = new_board(Board::Axrom, 32, 0, false);
+        assert_eq!(axrom.mirroring(), NametableMirroring::SingleScreenLower);
+        axrom.write_prg(0x8000, 0x13);
+        assert_eq!(axrom.read_prg(0x8000), 12);
+        assert_eq!(axrom.read_prg(0xC000), 14);
+        assert_eq!(axrom.mirroring(), NametableMirroring::SingleScreenUpper);
+        axrom.write_prg(0xFFFF, 0x00);
+        assert_eq!(axrom.mirroring(), NametableMirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn color_dreams_and_gxrom() {
+        let mut color_dreams = new_board(Board::ColorDreams, 16, 32, false);
+        color_dreams.write_prg(0x8000, 0x32);
+        assert_eq!(color_dreams.read_prg(0x8000), 8);
+        assert_eq!(color_dreams.read_chr(0x0000), 24);
+
+        let mut gxrom = new_board(Board::Gxrom, 16, 32, false);
+        gxrom.write_prg(0x8000, 0x23);
+        assert_eq!(gxrom.read_prg(0xC000), 10);
+        assert_eq!(gxrom.read_chr(0x1000), 28);
+    }
+
+    #[test]
+    fn bnrom_and_nina_001() {
+        let mut bnrom = new_board(Board::Bnrom, 16, 0, false);
+        bnrom.write_prg(0x8000, 3);
+        assert_eq!(bnrom.read_prg(0x8000), 12);
+        // no PRG RAM
+        bnrom.write_prg(0x7FFD, 0);
+        assert_eq!(bnrom.read_prg(0x8000), 12);
+
+        let mut nina = new_board(Board::Nina001, 8, 32, false);
+        nina.write_prg(0x7FFD, 1);
+        nina.write_prg(0x7FFE, 5);
+        nina.write_prg(0x7FFF, 2);
+        assert_eq!(nina.read_prg(0x8000), 4);
+        assert_eq!(nina.read_prg(0x7FFE), 5);
+        assert_eq!(nina.read_chr(0x0000), 20);
+        assert_eq!(nina.read_chr(0x1000), 8);
+        // writes to ROM don't reach the registers
+        nina.write_prg(0x8000, 0);
+        assert_eq!(nina.read_prg(0x8000), 4);
+    }
+
+    #[test]
+    fn bus_conflicts() {
+        // every ROM byte holds its 8 KiB bank number, so the written value is ANDed with it
+        let mut uxrom = new_board(Board::Uxrom, 16, 0, true);
+        uxrom.write_prg(0x8000, 0x03);
+        assert_eq!(uxrom.read_prg(0x8000), 0);
+        // $C000 holds 14, 3 & 14 selects bank 2
+        uxrom.write_prg(0xC000, 0x03);
+        assert_eq!(uxrom.read_prg(0x8000), 4);
+        // $8000 holds 4 now, 6 & 4 selects bank 4
+        uxrom.write_prg(0x8000, 0x06);
+        assert_eq!(uxrom.read_prg(0x8000), 8);
+    }
+}
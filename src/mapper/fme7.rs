@@ -0,0 +1,232 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+const PRG_RAM_SELECT_BIT: u8 = 6;
+const PRG_RAM_ENABLE_BIT: u8 = 7;
+
+/// Mapper 69, the Sunsoft FME-7 (and the 5A and 5B, which add expansion audio)
+///
+/// $8000-$9FFF selects one of 16 commands, $A000-$BFFF writes its parameter:
+///
+/// * 0-7: the 1 KiB CHR banks
+/// * 8: the 8 KiB bank at $6000 (bits 0-5) and whether it is PRG RAM (bit 6) and enabled (bit 7),
+///   with bit 6 clear it is PRG ROM
+/// * 9-B: the 8 KiB PRG banks at $8000, $A000 and $C000, the last bank is fixed at $E000
+/// * C: mirroring
+/// * D: IRQ enable (bit 0) and counter enable (bit 7), acknowledges the IRQ
+/// * E, F: the low and high byte of the IRQ counter
+///
+/// The 16 bit IRQ counter is decremented every CPU cycle while it is enabled and asserts the IRQ
+/// when it wraps around from 0. The audio registers at $C000-$FFFF are not emulated.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    command: u8,
+    chr_banks: [u8; 8],
+    /// The banks at $6000, $8000, $A000 and $C000
+    prg_banks: [u8; 4],
+    mirroring: NametableMirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq: bool,
+}
+
+impl Fme7 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, ram_fill: RamFill) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: NametableMirroring::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq: false,
+        }
+    }
+
+    fn prg_ram_bit(&self, bit: u8) -> bool {
+        self.prg_banks[0] & (1 << bit) != 0
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => self.prg_banks[0] = data,
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x8] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x03 {
+                    0 => NametableMirroring::Vertical,
+                    1 => NametableMirroring::Horizontal,
+                    2 => NametableMirroring::SingleScreenLower,
+                    _ => NametableMirroring::SingleScreenUpper,
+                };
+            }
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.counter_enabled = data & 0x80 != 0;
+                self.irq = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | data as u16,
+            _ => self.counter = (self.counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        bank_offset(bank as usize, CHR_BANK_SIZE, addr, self.chr.len())
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None if self.prg_ram_bit(PRG_RAM_ENABLE_BIT) => self.prg_ram.read(addr - 0x6000),
+            // open bus
+            None => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF
+                if self.prg_ram_bit(PRG_RAM_SELECT_BIT) && self.prg_ram_bit(PRG_RAM_ENABLE_BIT) =>
+            {
+                self.prg_ram.write(addr - 0x6000, data);
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            // PRG ROM at $6000 and expansion audio
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let len = self.prg_rom.len();
+        let bank = match addr {
+            0x6000..=0x7FFF if self.prg_ram_bit(PRG_RAM_SELECT_BIT) => return None,
+            0xE000..=0xFFFF => len / PRG_BANK_SIZE - 1,
+            _ => (self.prg_banks[(addr as usize - 0x6000) / PRG_BANK_SIZE] & 0x3F) as usize,
+        };
+        Some(bank_offset(bank, PRG_BANK_SIZE, addr, len))
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn tick(&mut self) {
+        if !self.counter_enabled {
+            return;
+        }
+        self.counter = self.counter.wrapping_sub(1);
+        if self.counter == 0xFFFF && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::fme7::Fme7;
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+
+    fn new_fme7() -> Fme7 {
+        let (prg_rom, chr_rom) = banked_rom(32, 256);
+        Fme7::new(prg_rom, chr_rom, RamFill::Zeros)
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, parameter: u8) {
+        fme7.write_prg(0x8000, command);
+        fme7.write_prg(0xA000, parameter);
+    }
+
+    #[test]
+    fn banks_and_mirroring() {
+        let mut fme7 = new_fme7();
+        for (bank, register) in [3, 4, 5].into_iter().zip(0x9..) {
+            command(&mut fme7, register, bank);
+        }
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| fme7.read_prg(addr));
+        assert_eq!(banks, [3, 4, 5, 31]);
+
+        command(&mut fme7, 0x7, 0xAB);
+        assert_eq!(fme7.read_chr(0x1C00), 0xAB);
+        assert_eq!(fme7.chr_rom_offset(0x1C10), Some(0xAB * 0x400 + 0x10));
+
+        command(&mut fme7, 0xC, 0x02);
+        assert_eq!(fme7.mirroring(), NametableMirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn prg_ram_and_rom_at_6000() {
+        let mut fme7 = new_fme7();
+        // ROM bank 7
+        command(&mut fme7, 0x8, 0x07);
+        assert_eq!(fme7.read_prg(0x6000), 7);
+        assert_eq!(fme7.prg_rom_offset(0x6001), Some(7 * 0x2000 + 1));
+        fme7.write_prg(0x6000, 0x42);
+        assert_eq!(fme7.read_prg(0x6000), 7);
+
+        // RAM, disabled and enabled
+        command(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.prg_rom_offset(0x6000), None);
+        fme7.write_prg(0x6000, 0x42);
+        assert_eq!(fme7.read_prg(0x6000), 0);
+        command(&mut fme7, 0x8, 0xC0);
+        fme7.write_prg(0x6000, 0x42);
+        assert_eq!(fme7.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut fme7 = new_fme7();
+        command(&mut fme7, 0xE, 0x02);
+        command(&mut fme7, 0xF, 0x00);
+        command(&mut fme7, 0xD, 0x81);
+
+        // counts down from 2 and wraps on the third cycle
+        for _ in 0..2 {
+            fme7.tick();
+        }
+        assert!(!fme7.irq());
+        fme7.tick();
+        assert!(fme7.irq());
+
+        // acknowledged by writing the control, the counter keeps running
+        command(&mut fme7, 0xD, 0x80);
+        assert!(!fme7.irq());
+        for _ in 0..0x10000 {
+            fme7.tick();
+        }
+        assert!(!fme7.irq());
+    }
+}
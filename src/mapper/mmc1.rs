@@ -0,0 +1,322 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+/// The largest PRG ROM that the 4 bits of the PRG bank register can address
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// The marker bit that reaches bit 0 of the shift register with the fifth write
+const SHIFT_REGISTER_EMPTY: u8 = 1 << 4;
+
+const CONTROL_PRG_MODE_MASK: u8 = 0b0_1100;
+const CONTROL_CHR_4K_BIT: u8 = 4;
+const PRG_RAM_DISABLE_BIT: u8 = 4;
+
+/// Mapper 1, the Nintendo MMC1 (SxROM boards)
+///
+/// The registers are loaded serially: five writes to $8000-$FFFF shift bit 0 of the data into a
+/// shift register, the fifth write copies it into the register selected by bits 13-14 of its
+/// address. A write with bit 7 set clears the shift register and selects PRG mode 3.
+///
+/// * Control ($8000-$9FFF): mirroring (bits 0-1), PRG mode (bits 2-3) and CHR mode (bit 4)
+/// * CHR bank 0 ($A000-$BFFF): the 4 KiB bank at PPU $0000, or the 8 KiB bank at $0000
+/// * CHR bank 1 ($C000-$DFFF): the 4 KiB bank at PPU $1000, ignored in 8 KiB mode
+/// * PRG bank ($E000-$FFFF): the 16 KiB bank (bits 0-3) and PRG RAM disable (bit 4)
+///
+/// SUROM boards with 512 KiB of PRG ROM use bit 4 of the CHR bank 0 register to select the
+/// 256 KiB half of PRG ROM.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, ram_fill: RamFill) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            shift_register: SHIFT_REGISTER_EMPTY,
+            // the last bank is fixed at $C000 after power-on, so the reset vector is mapped
+            control: CONTROL_PRG_MODE_MASK,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = data,
+            0xA000..=0xBFFF => self.chr_bank_0 = data,
+            0xC000..=0xDFFF => self.chr_bank_1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & (1 << PRG_RAM_DISABLE_BIT) == 0
+    }
+
+    fn prg_bank_at(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let last_bank = (PRG_OUTER_BANK_SIZE.min(self.prg_rom.len()) / PRG_BANK_SIZE) - 1;
+        let inner_bank = match ((self.control & CONTROL_PRG_MODE_MASK) >> 2, addr) {
+            // 32 KiB mode ignores the low bit of the bank number
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last_bank,
+        };
+        let outer_bank = if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            (self.chr_bank_0 & 0x10) as usize
+        } else {
+            0
+        };
+        outer_bank | inner_bank
+    }
+
+    fn chr_bank_at(&self, addr: u16) -> usize {
+        let bank_4k = self.control & (1 << CONTROL_CHR_4K_BIT) != 0;
+        match (bank_4k, addr) {
+            (true, 0x0000..=0x0FFF) => self.chr_bank_0 as usize,
+            (true, _) => self.chr_bank_1 as usize,
+            // 8 KiB mode ignores the low bit of the bank number
+            (false, 0x0000..=0x0FFF) => (self.chr_bank_0 & !1) as usize,
+            (false, _) => (self.chr_bank_0 | 1) as usize,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(self.chr_bank_at(addr), CHR_BANK_SIZE, addr, self.chr.len())
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None if self.prg_ram_enabled() => self.prg_ram.read(addr - 0x6000),
+            // open bus
+            None => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if self.prg_ram_enabled() {
+                self.prg_ram.write(addr - 0x6000, data);
+            }
+            return;
+        }
+
+        // the second write of read-modify-write instructions comes one cycle after the first
+        // and is ignored
+        let consecutive = self.last_write_cycle == Some(self.cycle.wrapping_sub(1));
+        self.last_write_cycle = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+
+        if data & 0x80 != 0 {
+            self.shift_register = SHIFT_REGISTER_EMPTY;
+            self.control |= CONTROL_PRG_MODE_MASK;
+            return;
+        }
+
+        let full = self.shift_register & 1 != 0;
+        self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+        if full {
+            self.write_register(addr, self.shift_register);
+            self.shift_register = SHIFT_REGISTER_EMPTY;
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        match self.control & 0b11 {
+            0 => NametableMirroring::SingleScreenLower,
+            1 => NametableMirroring::SingleScreenUpper,
+            2 => NametableMirroring::Vertical,
+            _ => NametableMirroring::Horizontal,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| {
+            bank_offset(
+                self.prg_bank_at(addr),
+                PRG_BANK_SIZE,
+                addr,
+                self.prg_rom.len(),
+            )
+        })
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn tick(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::mmc1::Mmc1;
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+
+    /// 16 KiB PRG bank n holds the 8 KiB banks 2n and 2n + 1, 4 KiB CHR bank n starts with the
+    /// 1 KiB bank 4n
+    fn new_mmc1() -> Mmc1 {
+        let (prg_rom, chr_rom) = banked_rom(32, 128);
+        Mmc1::new(prg_rom, chr_rom, RamFill::Zeros)
+    }
+
+    /// Loads a register through the shift register, with a cycle between the writes
+    fn write_serial(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.write_prg(addr, value >> bit);
+            mmc1.tick();
+            mmc1.tick();
+        }
+    }
+
+    #[test]
+    fn power_on_state() {
+        let mmc1 = new_mmc1();
+        assert_eq!(mmc1.read_prg(0x8000), 0);
+        assert_eq!(mmc1.read_prg(0xC000), 30);
+        assert_eq!(mmc1.read_prg(0xFFFF), 31);
+    }
+
+    #[test]
+    fn prg_bank_modes() {
+        let mut mmc1 = new_mmc1();
+        write_serial(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.read_prg(0x8000), 10);
+        assert_eq!(mmc1.read_prg(0xC000), 30);
+        assert_eq!(mmc1.prg_rom_offset(0x8123), Some(5 * 0x4000 + 0x123));
+
+        // fix the first bank at $8000
+        write_serial(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!(mmc1.read_prg(0x8000), 0);
+        assert_eq!(mmc1.read_prg(0xC000), 10);
+
+        // 32 KiB mode
+        write_serial(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!(mmc1.read_prg(0x8000), 8);
+        assert_eq!(mmc1.read_prg(0xC000), 10);
+    }
+
+    #[test]
+    fn chr_bank_modes() {
+        let mut mmc1 = new_mmc1();
+        write_serial(&mut mmc1, 0xA000, 7);
+        write_serial(&mut mmc1, 0xC000, 20);
+        // 8 KiB mode
+        assert_eq!(mmc1.read_chr(0x0000), 24);
+        assert_eq!(mmc1.read_chr(0x1000), 28);
+        assert_eq!(mmc1.chr_rom_offset(0x1010), Some(7 * 0x1000 + 0x10));
+
+        write_serial(&mut mmc1, 0x8000, 0b1_1100);
+        assert_eq!(mmc1.read_chr(0x0000), 28);
+        assert_eq!(mmc1.read_chr(0x1FFF), 83);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut mmc1 = new_mmc1();
+        for (control, mirroring) in [
+            (0, NametableMirroring::SingleScreenLower),
+            (1, NametableMirroring::SingleScreenUpper),
+            (2, NametableMirroring::Vertical),
+            (3, NametableMirroring::Horizontal),
+        ] {
+            write_serial(&mut mmc1, 0x9FFF, 0b0_1100 | control);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn reset_shift_register() {
+        let mut mmc1 = new_mmc1();
+        write_serial(&mut mmc1, 0x8000, 0b0_1000);
+        // two bits are discarded by the reset, which also selects PRG mode 3 again
+        mmc1.write_prg(0xE000, 1);
+        mmc1.tick();
+        mmc1.tick();
+        mmc1.write_prg(0xE000, 1);
+        mmc1.tick();
+        mmc1.tick();
+        mmc1.write_prg(0x8000, 0x80);
+        mmc1.tick();
+        mmc1.tick();
+        assert_eq!(mmc1.read_prg(0xC000), 30);
+        write_serial(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.read_prg(0x8000), 4);
+    }
+
+    #[test]
+    fn consecutive_writes_are_ignored() {
+        let mut mmc1 = new_mmc1();
+        // the dummy write of INC $E000 is followed by the real one on the next cycle
+        for _ in 0..5 {
+            mmc1.write_prg(0xE000, 0x01);
+            mmc1.tick();
+            mmc1.write_prg(0xE000, 0x02);
+            for _ in 0..6 {
+                mmc1.tick();
+            }
+        }
+        assert_eq!(mmc1.read_prg(0x8000), 30);
+    }
+
+    #[test]
+    fn prg_ram() {
+        let mut mmc1 = new_mmc1();
+        mmc1.write_prg(0x6000, 0x42);
+        assert_eq!(mmc1.read_prg(0x6000), 0x42);
+        assert_eq!(mmc1.prg_rom_offset(0x6000), None);
+
+        write_serial(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.read_prg(0x6000), 0);
+        mmc1.write_prg(0x6000, 0x43);
+        write_serial(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn chr_ram() {
+        let mut mmc1 = Mmc1::new(vec![0; 0x8000], Vec::new(), RamFill::Zeros);
+        write_serial(&mut mmc1, 0x8000, 0b1_1100);
+        write_serial(&mut mmc1, 0xC000, 0);
+        mmc1.write_chr(0x1010, 0x99);
+        assert_eq!(mmc1.read_chr(0x0010), 0x99);
+        assert_eq!(mmc1.chr_rom_offset(0x0010), None);
+    }
+}
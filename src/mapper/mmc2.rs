@@ -0,0 +1,247 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// The two chips that switch CHR banks when the PPU fetches tile $FD or $FE
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip {
+    /// Mapper 9 (PxROM): an 8 KiB bank at $8000, the last three 8 KiB banks are fixed
+    Mmc2,
+    /// Mapper 10 (FxROM): a 16 KiB bank at $8000, the last one is fixed at $C000, and 8 KiB of
+    /// PRG RAM
+    Mmc4,
+}
+
+/// Mapper 9 and 10, the Nintendo MMC2 and MMC4
+///
+/// Each half of the pattern tables has a latch and two 4 KiB CHR banks, the latch selects the
+/// bank that is shown. It is set to $FD or $FE after the PPU fetched the high bit plane of the
+/// tile with that number from its half: the MMC2 only reacts to the first row of the tile in the
+/// lower half ($0FD8 and $0FE8), everything else to any row ($xFD8-$xFDF and $xFE8-$xFEF).
+///
+/// * $A000: the PRG bank at $8000
+/// * $B000, $C000: the CHR banks at $0000 for latch $FD and $FE
+/// * $D000, $E000: the CHR banks at $1000 for latch $FD and $FE
+/// * $F000: mirroring
+pub struct Mmc2 {
+    chip: Chip,
+    prg_rom: Vec<u8>,
+    prg_ram: Option<Ram>,
+    chr: Chr,
+    prg_bank: u8,
+    /// The banks of both halves for latch $FD and $FE
+    chr_banks: [[u8; 2]; 2],
+    /// Whether the latch of each half is $FE
+    latches: [bool; 2],
+    mirroring: NametableMirroring,
+}
+
+impl Mmc2 {
+    pub fn new(
+        chip: Chip,
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: NametableMirroring,
+        ram_fill: RamFill,
+    ) -> Self {
+        let prg_ram = (chip == Chip::Mmc4).then(|| Ram::with_fill(PRG_RAM_SIZE, ram_fill));
+        Self {
+            chip,
+            prg_rom,
+            prg_ram,
+            chr: Chr::new(chr_rom),
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            // undefined at power-on, games set the banks of both latch values
+            latches: [true; 2],
+            mirroring,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize & 1;
+        let bank = self.chr_banks[half][self.latches[half] as usize];
+        bank_offset(bank as usize, CHR_BANK_SIZE, addr, self.chr.len())
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match (self.prg_rom_offset(addr), self.prg_ram.as_ref()) {
+            (Some(offset), _) => self.prg_rom[offset],
+            (None, Some(prg_ram)) => prg_ram.read(addr - 0x6000),
+            // open bus
+            (None, None) => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                if let Some(prg_ram) = self.prg_ram.as_mut() {
+                    prg_ram.write(addr - 0x6000, data);
+                }
+            }
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xEFFF => {
+                let register = (addr - 0xB000) as usize >> 12;
+                self.chr_banks[register / 2][register % 2] = data & 0x1F;
+            }
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 1 == 0 {
+                    NametableMirroring::Vertical
+                } else {
+                    NametableMirroring::Horizontal
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let len = self.prg_rom.len();
+        let (size, bank) = match (self.chip, addr) {
+            (Chip::Mmc2, 0x8000..=0x9FFF) => (0x2000, self.prg_bank as usize),
+            // the last three banks
+            (Chip::Mmc2, _) => (0x2000, len / 0x2000 - 4 + (addr as usize - 0x8000) / 0x2000),
+            (Chip::Mmc4, 0x8000..=0xBFFF) => (0x4000, self.prg_bank as usize),
+            (Chip::Mmc4, _) => (0x4000, len / 0x4000 - 1),
+        };
+        Some(bank_offset(bank, size, addr, len))
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        if addr >= 0x2000 {
+            return;
+        }
+        let half = (addr >> 12) as usize & 1;
+        let tile_addr = if self.chip == Chip::Mmc2 && half == 0 {
+            addr
+        } else {
+            addr & !0x07
+        };
+        match tile_addr & 0x0FFF {
+            0x0FD8 => self.latches[half] = false,
+            0x0FE8 => self.latches[half] = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::mmc2::{Chip, Mmc2};
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::{Memory, RamFill};
+    use crate::nes_rom::NametableMirroring;
+    use crate::ppu::Ppu;
+
+    /// Selects the 4 KiB CHR banks 4-7, which start with the 1 KiB banks 16, 20, 24 and 28
+    fn new_chip(chip: Chip) -> Mmc2 {
+        let (prg_rom, chr_rom) = banked_rom(16, 128);
+        let mut mmc2 = Mmc2::new(
+            chip,
+            prg_rom,
+            chr_rom,
+            NametableMirroring::Vertical,
+            RamFill::Zeros,
+        );
+        for (register, bank) in [0xB000, 0xC000, 0xD000, 0xE000].into_iter().zip(4..) {
+            mmc2.write_prg(register, bank);
+        }
+        mmc2
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mmc2 = new_chip(Chip::Mmc2);
+        mmc2.write_prg(0xA000, 5);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc2.read_prg(addr));
+        assert_eq!(banks, [5, 13, 14, 15]);
+        assert_eq!(mmc2.prg_rom_offset(0xC001), Some(14 * 0x2000 + 1));
+        // no PRG RAM
+        mmc2.write_prg(0x6000, 0x42);
+        assert_eq!(mmc2.read_prg(0x6000), 0);
+
+        let mut mmc4 = new_chip(Chip::Mmc4);
+        mmc4.write_prg(0xA000, 3);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc4.read_prg(addr));
+        assert_eq!(banks, [6, 7, 14, 15]);
+        mmc4.write_prg(0x6000, 0x42);
+        assert_eq!(mmc4.read_prg(0x6000), 0x42);
+
+        mmc4.write_prg(0xF000, 1);
+        assert_eq!(mmc4.mirroring(), NametableMirroring::Horizontal);
+    }
+
+    #[test]
+    fn chr_latches() {
+        for chip in [Chip::Mmc2, Chip::Mmc4] {
+            let mut mmc2 = new_chip(chip);
+            assert_eq!([mmc2.read_chr(0x0000), mmc2.read_chr(0x1000)], [20, 28]);
+
+            mmc2.ppu_address(0x0FD8);
+            mmc2.ppu_address(0x1FDF);
+            assert_eq!([mmc2.read_chr(0x0000), mmc2.read_chr(0x1000)], [16, 24]);
+            assert_eq!(mmc2.chr_rom_offset(0x1010), Some(6 * 0x1000 + 0x10));
+
+            // the low bit plane doesn't switch
+            mmc2.ppu_address(0x1FE0);
+            assert_eq!(mmc2.read_chr(0x1000), 24);
+            mmc2.ppu_address(0x1FE8);
+            assert_eq!(mmc2.read_chr(0x1000), 28);
+
+            // other rows of the tile only switch the lower half of the MMC4
+            mmc2.ppu_address(0x0FE9);
+            let expected = if chip == Chip::Mmc2 { 16 } else { 20 };
+            assert_eq!(mmc2.read_chr(0x0000), expected);
+
+            // addresses outside the pattern tables are ignored
+            mmc2.ppu_address(0x2FD8);
+            assert_eq!(mmc2.read_chr(0x1000), 28);
+        }
+    }
+
+    #[test]
+    fn latch_switched_by_sprite_fetch() {
+        let mut ppu = Ppu::new(Box::new(new_chip(Chip::Mmc2)));
+        // let the warm-up pass, then render with sprites at $1000 and a sprite with tile $FD at
+        // Y 10, which is fetched at the end of line 10
+        ppu.tick(262 * 341);
+        ppu.write_oam_addr(0);
+        for data in [10, 0xFD, 0, 0] {
+            ppu.write_oam_data(data);
+        }
+        for addr in 0x2000..0x2400 {
+            ppu.memory.write(addr, 0);
+        }
+        ppu.write_ppu_ctrl(0x08);
+        ppu.write_ppu_mask(0x18);
+
+        ppu.tick(10 * 341);
+        assert_eq!(ppu.memory.mapper.read_chr(0x1000), 28);
+        ppu.tick(341);
+        assert_eq!(ppu.memory.mapper.read_chr(0x1000), 24);
+    }
+}
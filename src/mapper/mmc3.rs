@@ -0,0 +1,445 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+const BANK_SELECT_PRG_MODE_BIT: u8 = 6;
+const BANK_SELECT_CHR_INVERSION_BIT: u8 = 7;
+const PRG_RAM_WRITE_PROTECT_BIT: u8 = 6;
+const PRG_RAM_ENABLE_BIT: u8 = 7;
+
+/// A12 has to stay low for this many CPU cycles before a rising edge clocks the IRQ counter,
+/// which filters the short low phases between fetches from the same pattern table
+const A12_LOW_CYCLES: u64 = 3;
+
+/// Mapper 4, the Nintendo MMC3 (TxROM boards)
+///
+/// Even and odd addresses of each 8 KiB range select a different register:
+///
+/// * $8000: the bank register written by $8001 (bits 0-2), PRG mode (bit 6) and CHR inversion
+///   (bit 7)
+/// * $8001: R0-R1 are 2 KiB CHR banks at $0000 and $0800, R2-R5 1 KiB CHR banks at
+///   $1000-$1C00, the two halves of the pattern tables are swapped with CHR inversion. R6 is
+///   the 8 KiB PRG bank at $8000 ($C000 in PRG mode 1), R7 the one at $A000, the second to last
+///   bank is at the other one of $8000 and $C000 and the last bank at $E000.
+/// * $A000: mirroring, $A001: PRG RAM write protect (bit 6) and enable (bit 7)
+/// * $C000: the value the IRQ counter is reloaded with, $C001: reloads the counter with the
+///   next clock
+/// * $E000: disables and acknowledges the IRQ, $E001: enables it
+///
+/// The IRQ counter is clocked by rising edges of PPU A12, which happen once per scanline when
+/// the background and sprites use different pattern tables. The IRQ is asserted when the
+/// counter reaches 0 after a clock.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: NametableMirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    cycle: u64,
+    /// The CPU cycle A12 was last seen low after being high
+    a12_low_since: Option<u64>,
+}
+
+impl Mmc3 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: NametableMirroring,
+        ram_fill: RamFill,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            // the power-on state is undefined, games that never write $A001 expect working RAM
+            prg_ram_protect: 1 << PRG_RAM_ENABLE_BIT,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            cycle: 0,
+            a12_low_since: None,
+        }
+    }
+
+    fn bank_select_bit(&self, bit: u8) -> bool {
+        self.bank_select & (1 << bit) != 0
+    }
+
+    fn prg_ram_protect_bit(&self, bit: u8) -> bool {
+        self.prg_ram_protect & (1 << bit) != 0
+    }
+
+    fn prg_bank_at(&self, addr: u16) -> usize {
+        let second_last = self.prg_rom.len() / PRG_BANK_SIZE - 2;
+        let swapped = self.bank_select_bit(BANK_SELECT_PRG_MODE_BIT);
+        match (addr, swapped) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => (self.banks[6] & 0x3F) as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => (self.banks[7] & 0x3F) as usize,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank_at(&self, addr: u16) -> usize {
+        let addr = if self.bank_select_bit(BANK_SELECT_CHR_INVERSION_BIT) {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        match addr {
+            // the 2 KiB banks ignore the low bit
+            0x0000..=0x07FF => (self.banks[0] & 0xFE) as usize + ((addr >> 10) & 1) as usize,
+            0x0800..=0x0FFF => (self.banks[1] & 0xFE) as usize + ((addr >> 10) & 1) as usize,
+            _ => self.banks[2 + ((addr - 0x1000) >> 10) as usize] as usize,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(self.chr_bank_at(addr), CHR_BANK_SIZE, addr, self.chr.len())
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None if self.prg_ram_protect_bit(PRG_RAM_ENABLE_BIT) => {
+                self.prg_ram.read(addr - 0x6000)
+            }
+            // open bus
+            None => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF
+                if self.prg_ram_protect_bit(PRG_RAM_ENABLE_BIT)
+                    && !self.prg_ram_protect_bit(PRG_RAM_WRITE_PROTECT_BIT) =>
+            {
+                self.prg_ram.write(addr - 0x6000, data);
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 0x07) as usize] = data,
+            0xA000..=0xBFFF if even => {
+                self.mirroring = if data & 1 == 0 {
+                    NametableMirroring::Vertical
+                } else {
+                    NametableMirroring::Horizontal
+                };
+            }
+            0xA000..=0xBFFF => self.prg_ram_protect = data,
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| {
+            bank_offset(
+                self.prg_bank_at(addr),
+                PRG_BANK_SIZE,
+                addr,
+                self.prg_rom.len(),
+            )
+        })
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn tick(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        if addr & 0x1000 == 0 {
+            self.a12_low_since.get_or_insert(self.cycle);
+        } else if let Some(low_since) = self.a12_low_since.take() {
+            if self.cycle.wrapping_sub(low_since) >= A12_LOW_CYCLES {
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::bus::Bus;
+    use crate::cpu::Cpu;
+    use crate::mapper::mmc3::Mmc3;
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::{NametableMirroring, NesRom};
+    use crate::ppu::Ppu;
+
+    fn new_mmc3() -> Mmc3 {
+        let (prg_rom, chr_rom) = banked_rom(32, 256);
+        Mmc3::new(
+            prg_rom,
+            chr_rom,
+            NametableMirroring::Vertical,
+            RamFill::Zeros,
+        )
+    }
+
+    fn set_bank(mmc3: &mut Mmc3, bank_select: u8, register: u8, bank: u8) {
+        mmc3.write_prg(0x8000, bank_select | register);
+        mmc3.write_prg(0x8001, bank);
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mmc3 = new_mmc3();
+        set_bank(&mut mmc3, 0x00, 6, 3);
+        set_bank(&mut mmc3, 0x00, 7, 9);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc3.read_prg(addr));
+        assert_eq!(banks, [3, 9, 30, 31]);
+        assert_eq!(mmc3.prg_rom_offset(0xA001), Some(9 * 0x2000 + 1));
+
+        // PRG mode 1 swaps $8000 and $C000
+        mmc3.write_prg(0x8000, 0x40);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc3.read_prg(addr));
+        assert_eq!(banks, [30, 9, 3, 31]);
+    }
+
+    #[test]
+    fn chr_banks() {
+        let mut mmc3 = new_mmc3();
+        for (register, bank) in [
+            (0, 0x11),
+            (1, 0x20),
+            (2, 0x30),
+            (3, 0x31),
+            (4, 0x40),
+            (5, 0x50),
+        ] {
+            set_bank(&mut mmc3, 0x00, register, bank);
+        }
+        let banks = (0..8).map(|i| mmc3.read_chr(i * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks, [0x10, 0x11, 0x20, 0x21, 0x30, 0x31, 0x40, 0x50]);
+        assert_eq!(mmc3.chr_rom_offset(0x1C10), Some(0x50 * 0x400 + 0x10));
+
+        // CHR inversion swaps the pattern tables
+        mmc3.write_prg(0x8000, 0x80);
+        let banks = (0..8).map(|i| mmc3.read_chr(i * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks, [0x30, 0x31, 0x40, 0x50, 0x10, 0x11, 0x20, 0x21]);
+    }
+
+    #[test]
+    fn mirroring_and_prg_ram_protect() {
+        let mut mmc3 = new_mmc3();
+        mmc3.write_prg(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), NametableMirroring::Horizontal);
+        mmc3.write_prg(0xBFFE, 0);
+        assert_eq!(mmc3.mirroring(), NametableMirroring::Vertical);
+
+        mmc3.write_prg(0x6000, 0x42);
+        assert_eq!(mmc3.read_prg(0x6000), 0x42);
+        // write protected
+        mmc3.write_prg(0xA001, 0xC0);
+        mmc3.write_prg(0x6000, 0x43);
+        assert_eq!(mmc3.read_prg(0x6000), 0x42);
+        // disabled
+        mmc3.write_prg(0xA001, 0x00);
+        assert_eq!(mmc3.read_prg(0x6000), 0);
+        mmc3.write_prg(0x6000, 0x44);
+        mmc3.write_prg(0xA001, 0x80);
+        assert_eq!(mmc3.read_prg(0x6000), 0x42);
+    }
+
+    /// Moves A12 low and back high, with enough time in between to pass the filter
+    fn clock_a12(mmc3: &mut Mmc3) {
+        mmc3.ppu_address(0x0000);
+        for _ in 0..4 {
+            mmc3.tick();
+        }
+        mmc3.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut mmc3 = new_mmc3();
+        mmc3.write_prg(0xC000, 2);
+        mmc3.write_prg(0xC001, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        // the first clock reloads the counter
+        clock_a12(&mut mmc3);
+        clock_a12(&mut mmc3);
+        assert!(!mmc3.irq());
+        clock_a12(&mut mmc3);
+        assert!(mmc3.irq());
+
+        // acknowledged, the counter is reloaded after reaching 0
+        mmc3.write_prg(0xE000, 0);
+        mmc3.write_prg(0xE001, 0);
+        assert!(!mmc3.irq());
+        clock_a12(&mut mmc3);
+        clock_a12(&mut mmc3);
+        assert!(!mmc3.irq());
+        clock_a12(&mut mmc3);
+        assert!(mmc3.irq());
+
+        // short low phases are filtered
+        mmc3.write_prg(0xE000, 0);
+        mmc3.write_prg(0xE001, 0);
+        for _ in 0..4 {
+            mmc3.ppu_address(0x0000);
+            mmc3.tick();
+            mmc3.ppu_address(0x1000);
+        }
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn scanline_irq_from_ppu_fetches() {
+        let mut mmc3 = new_mmc3();
+        // IRQ after 10 lines
+        mmc3.write_prg(0xC000, 9);
+        mmc3.write_prg(0xC001, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        let mut ppu = Ppu::new(Box::new(mmc3));
+        // let the warm-up pass, then render with the background at $0000 and sprites at $1000
+        ppu.tick(262 * 341);
+        ppu.write_ppu_ctrl(0x08);
+        ppu.write_ppu_mask(0x18);
+
+        // clocked like the bus does, 3 dots per CPU cycle
+        let mut irq_line = None;
+        for _ in 0..262 * 341 / 3 {
+            ppu.memory.mapper.tick();
+            ppu.tick(3);
+            if ppu.memory.mapper.irq() {
+                irq_line = Some(ppu.scanline);
+                break;
+            }
+        }
+        // the pre-render line clocks the reload, lines 0-9 count down to 0 at the first sprite
+        // fetch from $1000
+        assert_eq!(irq_line, Some(9));
+    }
+
+    /// Runs one of blargg's mmc3_test ROMs. They report through PRG RAM once $6001-$6003 hold
+    /// DE B0 61: $6000 is $80 while the test runs and the result after that, 0 on success, with
+    /// the text at $6004 explaining it. 6-MMC6.nes is left out, the MMC6 isn't emulated.
+    fn mmc3_test(name: &str) {
+        let path = format!("./vendor/nes-test-roms/mmc3_test/{}", name);
+        let rom = NesRom::read_from_file(&path).unwrap_or_else(|e| {
+            panic!(
+                "{}: {}, check out the submodules with `git submodule update --init`",
+                path, e
+            )
+        });
+        let mut cpu = Cpu::with_nes_options(Bus::new(rom));
+        cpu.power_on();
+
+        let mut frames = 0;
+        let status = loop {
+            if let Err(e) = cpu.tick() {
+                panic!("{} after {} frames: {}", name, frames, e);
+            }
+            if !cpu.poll_new_frame() {
+                continue;
+            }
+            frames += 1;
+            let signature = [0x6001, 0x6002, 0x6003].map(|addr| cpu.bus.peek(addr));
+            let status = cpu.bus.peek(0x6000);
+            if signature == [0xDE, 0xB0, 0x61] && status != 0x80 {
+                break status;
+            }
+            assert!(frames < 60 * 60, "{} didn't finish within a minute", name);
+        };
+
+        let text = (0x6004..)
+            .map(|addr| cpu.bus.peek(addr))
+            .take_while(|&c| c != 0)
+            .map(char::from)
+            .collect::<String>();
+        assert_eq!(
+            status, 0,
+            "{} failed with status {}:\n{}",
+            name, status, text
+        );
+    }
+
+    #[test]
+    fn mmc3_test_clocking() {
+        mmc3_test("1-clocking.nes");
+    }
+
+    #[test]
+    fn mmc3_test_details() {
+        mmc3_test("2-details.nes");
+    }
+
+    #[test]
+    fn mmc3_test_a12_clocking() {
+        mmc3_test("3-A12_clocking.nes");
+    }
+
+    #[test]
+    fn mmc3_test_scanline_timing() {
+        mmc3_test("4-scanline_timing.nes");
+    }
+
+    #[test]
+    fn mmc3_test_mmc3() {
+        mmc3_test("5-MMC3.nes");
+    }
+}
@@ -0,0 +1,66 @@
+use crate::mapper::{Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+/// Mapper 0, 16 or 32 KiB of PRG ROM and 8 KiB of CHR without any banking
+///
+/// The mirroring is hardwired and taken from the header. Family BASIC has PRG RAM at
+/// $6000-$7FFF, it is mapped for all games since the header doesn't tell.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    mirroring: NametableMirroring,
+}
+
+impl Nrom {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: NametableMirroring,
+        ram_fill: RamFill,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None => self.prg_ram.read(addr - 0x6000),
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if (0x6000..0x8000).contains(&addr) {
+            self.prg_ram.write(addr - 0x6000, data);
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr as usize, data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        // 16 KiB of ROM are mirrored at $C000
+        (addr >= 0x8000).then(|| (addr as usize - 0x8000) % self.prg_rom.len())
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(addr as usize)
+    }
+}
@@ -0,0 +1,398 @@
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// The CPU address lines that the board connects to the register select inputs A0 and A1 of a
+/// Konami VRC
+///
+/// Mapper numbers 21, 23 and 25 each stand for two boards with different lines. Both are
+/// decoded at once since games only write to the addresses of their own board, e.g. mapper 23
+/// selects register 1 with A0 (VRC2b, VRC4f) or A2 (VRC4e).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wiring {
+    /// Mapper 21: VRC4a (A1, A2) and VRC4c (A6, A7)
+    Vrc4AC,
+    /// Mapper 22: VRC2a (A1, A0), which drops the low bit of the CHR banks
+    Vrc2A,
+    /// Mapper 23: VRC2b and VRC4f (A0, A1), VRC4e (A2, A3)
+    Vrc4EF,
+    /// Mapper 25: VRC2c and VRC4b (A1, A0), VRC4d (A3, A2)
+    Vrc4BD,
+    /// Mapper 24: VRC6a (A0, A1)
+    Vrc6A,
+    /// Mapper 26: VRC6b (A1, A0)
+    Vrc6B,
+}
+
+impl Wiring {
+    /// The register `addr` selects, as the address of its 4 KiB range plus 0-3
+    pub fn register(self, addr: u16) -> u16 {
+        let (a0, a1) = match self {
+            Wiring::Vrc4AC => (0x02 | 0x40, 0x04 | 0x80),
+            Wiring::Vrc2A => (0x02, 0x01),
+            Wiring::Vrc4EF => (0x01 | 0x04, 0x02 | 0x08),
+            Wiring::Vrc4BD => (0x02 | 0x08, 0x01 | 0x04),
+            Wiring::Vrc6A => (0x01, 0x02),
+            Wiring::Vrc6B => (0x02, 0x01),
+        };
+        (addr & 0xF000) | (addr & a0 != 0) as u16 | ((addr & a1 != 0) as u16) << 1
+    }
+}
+
+/// The IRQ counter of the VRC4 and VRC6
+///
+/// The 8 bit counter counts up from the latch and asserts the IRQ when it overflows, which
+/// reloads it. In cycle mode it is clocked by every CPU cycle, in scanline mode by a prescaler
+/// that divides the CPU clock by 113.667, which is one clock per scanline.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    /// The dots left until the next clock in scanline mode, 3 per CPU cycle
+    prescaler: i16,
+    enabled: bool,
+    enabled_after_ack: bool,
+    cycle_mode: bool,
+    irq: bool,
+}
+
+const PRESCALER_RELOAD: i16 = 341;
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        Self {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_RELOAD,
+            enabled: false,
+            enabled_after_ack: false,
+            cycle_mode: false,
+            irq: false,
+        }
+    }
+
+    pub fn latch(&self) -> u8 {
+        self.latch
+    }
+
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    /// Sets the enable after acknowledgement (bit 0), enable (bit 1) and cycle mode (bit 2)
+    /// flags, enabling the counter reloads it
+    pub fn write_control(&mut self, data: u8) {
+        self.enabled_after_ack = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_RELOAD;
+        }
+        self.irq = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+        self.enabled = self.enabled_after_ack;
+    }
+
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += PRESCALER_RELOAD;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.irq = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+/// Mapper 21, 22, 23 and 25, the Konami VRC2 and VRC4
+///
+/// The VRC4 is treated as a superset of the VRC2, which lacks the IRQ counter, the PRG swap mode
+/// and the upper bit of the CHR banks. Registers with the register select inputs at 0-3:
+///
+/// * $8000: the 8 KiB PRG bank at $8000 ($C000 in swap mode), $A000: the one at $A000, the
+///   second to last bank is at the other one of $8000 and $C000 and the last bank at $E000
+/// * $9000: mirroring, $9002: PRG swap mode (bit 1), the VRC2 only has mirroring at $9000-$9003
+/// * $B000-$E003: the 1 KiB CHR banks, the even registers set the low 4 bits of a bank and the
+///   odd ones the upper 5 bits, e.g. $C002 and $C003 set the bank at $0C00
+/// * $F000, $F001: the low and high 4 bits of the IRQ latch, $F002: IRQ control, $F003:
+///   acknowledges the IRQ
+pub struct Vrc4 {
+    wiring: Wiring,
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: NametableMirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(
+        wiring: Wiring,
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: NametableMirroring,
+        ram_fill: RamFill,
+    ) -> Self {
+        Self {
+            wiring,
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        let bank = if self.wiring == Wiring::Vrc2A {
+            bank >> 1
+        } else {
+            bank
+        };
+        bank_offset(bank as usize, CHR_BANK_SIZE, addr, self.chr.len())
+    }
+
+    fn write_mirroring(&mut self, data: u8) {
+        let data = if self.wiring == Wiring::Vrc2A {
+            data & 0x01
+        } else {
+            data & 0x03
+        };
+        self.mirroring = match data {
+            0 => NametableMirroring::Vertical,
+            1 => NametableMirroring::Horizontal,
+            2 => NametableMirroring::SingleScreenLower,
+            _ => NametableMirroring::SingleScreenUpper,
+        };
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None => self.prg_ram.read(addr - 0x6000),
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            self.prg_ram.write(addr - 0x6000, data);
+            return;
+        }
+
+        match self.wiring.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1F,
+            0x9002 if self.wiring != Wiring::Vrc2A => self.prg_swap = data & 0x02 != 0,
+            0x9003 if self.wiring != Wiring::Vrc2A => {}
+            0x9000..=0x9003 => self.write_mirroring(data),
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                let index = ((register >> 12) - 0xB) as usize * 2 + ((register as usize >> 1) & 1);
+                let bank = &mut self.chr_banks[index];
+                *bank = if register & 1 == 0 {
+                    (*bank & 0x1F0) | (data as u16 & 0x0F)
+                } else {
+                    (*bank & 0x0F) | (data as u16 & 0x1F) << 4
+                };
+            }
+            0xF000 => self
+                .irq
+                .write_latch((self.irq.latch() & 0xF0) | (data & 0x0F)),
+            0xF001 => self
+                .irq
+                .write_latch((self.irq.latch() & 0x0F) | (data << 4)),
+            0xF002 => self.irq.write_control(data),
+            0xF003 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let second_last = self.prg_rom.len() / PRG_BANK_SIZE - 2;
+        let bank = match (addr, self.prg_swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            _ => second_last + 1,
+        };
+        Some(bank_offset(bank, PRG_BANK_SIZE, addr, self.prg_rom.len()))
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn tick(&mut self) {
+        self.irq.tick();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::vrc::{Vrc4, VrcIrq, Wiring};
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+
+    fn new_vrc4(wiring: Wiring) -> Vrc4 {
+        let (prg_rom, chr_rom) = banked_rom(32, 256);
+        Vrc4::new(
+            wiring,
+            prg_rom,
+            chr_rom,
+            NametableMirroring::Vertical,
+            RamFill::Zeros,
+        )
+    }
+
+    #[test]
+    fn wiring() {
+        // register 3 of each range for every board of the mapper numbers
+        assert_eq!(Wiring::Vrc4AC.register(0x9006), 0x9003);
+        assert_eq!(Wiring::Vrc4AC.register(0x90C0), 0x9003);
+        assert_eq!(Wiring::Vrc2A.register(0x9003), 0x9003);
+        assert_eq!(Wiring::Vrc2A.register(0x9001), 0x9002);
+        assert_eq!(Wiring::Vrc4EF.register(0x9003), 0x9003);
+        assert_eq!(Wiring::Vrc4EF.register(0x900C), 0x9003);
+        assert_eq!(Wiring::Vrc4EF.register(0x9004), 0x9001);
+        assert_eq!(Wiring::Vrc4BD.register(0x9002), 0x9001);
+        assert_eq!(Wiring::Vrc4BD.register(0x9004), 0x9002);
+        assert_eq!(Wiring::Vrc6A.register(0xB003), 0xB003);
+        assert_eq!(Wiring::Vrc6B.register(0xB001), 0xB002);
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut vrc4 = new_vrc4(Wiring::Vrc4EF);
+        vrc4.write_prg(0x8000, 3);
+        vrc4.write_prg(0xA000, 9);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| vrc4.read_prg(addr));
+        assert_eq!(banks, [3, 9, 30, 31]);
+
+        // swap mode, written through the VRC4e lines
+        vrc4.write_prg(0x9008, 0x02);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| vrc4.read_prg(addr));
+        assert_eq!(banks, [30, 9, 3, 31]);
+        assert_eq!(vrc4.prg_rom_offset(0xC001), Some(3 * 0x2000 + 1));
+
+        vrc4.write_prg(0x6000, 0x42);
+        assert_eq!(vrc4.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn chr_banks_and_mirroring() {
+        let mut vrc4 = new_vrc4(Wiring::Vrc4AC);
+        // $C002 and $C003 through the VRC4c lines
+        vrc4.write_prg(0xC080, 0x05);
+        vrc4.write_prg(0xC0C0, 0x1A);
+        assert_eq!(
+            vrc4.chr_rom_offset(0x0C10),
+            Some(0x1A5 * 0x400 % 0x40000 + 0x10)
+        );
+        vrc4.write_prg(0xB000, 0x07);
+        assert_eq!(vrc4.read_chr(0x0000), 7);
+
+        vrc4.write_prg(0x9000, 3);
+        assert_eq!(vrc4.mirroring(), NametableMirroring::SingleScreenUpper);
+
+        // VRC2a drops the low bit and only has two mirroring modes, also at $9002
+        let mut vrc2 = new_vrc4(Wiring::Vrc2A);
+        vrc2.write_prg(0xB000, 0x07);
+        assert_eq!(vrc2.read_chr(0x0000), 3);
+        vrc2.write_prg(0x9001, 3);
+        assert_eq!(vrc2.mirroring(), NametableMirroring::Horizontal);
+    }
+
+    #[test]
+    fn irq_cycle_mode() {
+        let mut vrc4 = new_vrc4(Wiring::Vrc4EF);
+        vrc4.write_prg(0xF000, 0x0D);
+        vrc4.write_prg(0xF001, 0x0F);
+        vrc4.write_prg(0xF002, 0x07);
+
+        // counts from $FD to $FF and overflows on the third cycle
+        for _ in 0..2 {
+            vrc4.tick();
+        }
+        assert!(!vrc4.irq());
+        vrc4.tick();
+        assert!(vrc4.irq());
+
+        // acknowledged, reloaded from the latch
+        vrc4.write_prg(0xF003, 0);
+        assert!(!vrc4.irq());
+        for _ in 0..3 {
+            vrc4.tick();
+        }
+        assert!(vrc4.irq());
+
+        // disabled by the acknowledgement without bit 0 of the control
+        vrc4.write_prg(0xF002, 0x06);
+        vrc4.write_prg(0xF003, 0);
+        for _ in 0..10 {
+            vrc4.tick();
+        }
+        assert!(!vrc4.irq());
+    }
+
+    #[test]
+    fn irq_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFE);
+        irq.write_control(0x02);
+
+        // two scanlines of 341 dots are 227.3 CPU cycles
+        for _ in 0..227 {
+            irq.tick();
+        }
+        assert!(!irq.irq());
+        irq.tick();
+        assert!(irq.irq());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::mapper::vrc::{VrcIrq, Wiring};
+use crate::mapper::{bank_offset, Chr, Mapper, PRG_RAM_SIZE};
+use crate::memory::{Memory, Ram, RamFill};
+use crate::nes_rom::NametableMirroring;
+
+const CHR_BANK_SIZE: usize = 0x0400;
+
+const BANKING_PRG_RAM_ENABLE_BIT: u8 = 7;
+const BANKING_CHR_A10_BIT: u8 = 5;
+
+/// Mapper 24 and 26, the Konami VRC6
+///
+/// Registers with the register select inputs at 0-3:
+///
+/// * $8000: the 16 KiB PRG bank at $8000, $C000: the 8 KiB PRG bank at $C000, the last bank is
+///   fixed at $E000
+/// * $B003: the CHR banking mode (bits 0-1), mirroring (bits 2-3), whether the 2 KiB CHR banks
+///   take A10 from the PPU or from the low bit of the bank (bit 5) and PRG RAM enable (bit 7)
+/// * $D000-$E003: CHR banks R0-R7, 1 KiB each in mode 0, R0-R3 are 2 KiB banks in mode 1, R0-R3
+///   1 KiB banks at $0000 and R4-R5 2 KiB banks at $1000 in modes 2 and 3
+/// * $F000: the IRQ latch, $F001: IRQ control, $F002: acknowledges the IRQ
+///
+/// The expansion audio at $9000-$B002 is not emulated. Nametables in CHR ROM aren't either,
+/// bits 2-3 of $B003 select the mirroring like in mode 0 in all modes.
+pub struct Vrc6 {
+    wiring: Wiring,
+    prg_rom: Vec<u8>,
+    prg_ram: Ram,
+    chr: Chr,
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    banking: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(wiring: Wiring, prg_rom: Vec<u8>, chr_rom: Vec<u8>, ram_fill: RamFill) -> Self {
+        Self {
+            wiring,
+            prg_rom,
+            prg_ram: Ram::with_fill(PRG_RAM_SIZE, ram_fill),
+            chr: Chr::new(chr_rom),
+            prg_banks: [0, 0],
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn banking_bit(&self, bit: u8) -> bool {
+        self.banking & (1 << bit) != 0
+    }
+
+    fn chr_bank_at(&self, addr: u16) -> usize {
+        let slot = (addr >> 10) as usize & 7;
+        let two_kib = |register: usize| {
+            let bank = self.chr_banks[register] as usize;
+            if self.banking_bit(BANKING_CHR_A10_BIT) {
+                (bank & !1) | (slot & 1)
+            } else {
+                bank
+            }
+        };
+        match (self.banking & 0x03, slot) {
+            (0, _) => self.chr_banks[slot] as usize,
+            (1, _) => two_kib(slot / 2),
+            (_, 0..=3) => self.chr_banks[slot] as usize,
+            (_, _) => two_kib(slot / 2 + 2),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(self.chr_bank_at(addr), CHR_BANK_SIZE, addr, self.chr.len())
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&self, addr: u16) -> u8 {
+        match self.prg_rom_offset(addr) {
+            Some(offset) => self.prg_rom[offset],
+            None if self.banking_bit(BANKING_PRG_RAM_ENABLE_BIT) => {
+                self.prg_ram.read(addr - 0x6000)
+            }
+            // open bus
+            None => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            if self.banking_bit(BANKING_PRG_RAM_ENABLE_BIT) {
+                self.prg_ram.write(addr - 0x6000, data);
+            }
+            return;
+        }
+
+        match self.wiring.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x0F,
+            0xB003 => self.banking = data,
+            0xC000..=0xC003 => self.prg_banks[1] = data & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let index = ((register >> 12) - 0xD) as usize * 4 + (register & 0x03) as usize;
+                self.chr_banks[index] = data;
+            }
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            // expansion audio
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_offset(addr), data);
+    }
+
+    fn mirroring(&self) -> NametableMirroring {
+        match (self.banking >> 2) & 0x03 {
+            0 => NametableMirroring::Vertical,
+            1 => NametableMirroring::Horizontal,
+            2 => NametableMirroring::SingleScreenLower,
+            _ => NametableMirroring::SingleScreenUpper,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let len = self.prg_rom.len();
+        match addr {
+            0x8000..=0xBFFF => Some(bank_offset(self.prg_banks[0] as usize, 0x4000, addr, len)),
+            0xC000..=0xDFFF => Some(bank_offset(self.prg_banks[1] as usize, 0x2000, addr, len)),
+            0xE000..=0xFFFF => Some(bank_offset(len / 0x2000 - 1, 0x2000, addr, len)),
+            _ => None,
+        }
+    }
+
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn tick(&mut self) {
+        self.irq.tick();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::vrc::Wiring;
+    use crate::mapper::vrc6::Vrc6;
+    use crate::mapper::{banked_rom, Mapper};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+
+    fn new_vrc6(wiring: Wiring) -> Vrc6 {
+        let (prg_rom, chr_rom) = banked_rom(32, 256);
+        Vrc6::new(wiring, prg_rom, chr_rom, RamFill::Zeros)
+    }
+
+    #[test]
+    fn prg_banks_and_ram() {
+        let mut vrc6 = new_vrc6(Wiring::Vrc6A);
+        vrc6.write_prg(0x8000, 3);
+        vrc6.write_prg(0xC000, 9);
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| vrc6.read_prg(addr));
+        assert_eq!(banks, [6, 7, 9, 31]);
+        assert_eq!(vrc6.prg_rom_offset(0xA001), Some(7 * 0x2000 + 1));
+
+        // PRG RAM is disabled until bit 7 of $B003 is set
+        vrc6.write_prg(0x6000, 0x42);
+        assert_eq!(vrc6.read_prg(0x6000), 0);
+        vrc6.write_prg(0xB003, 0x80);
+        vrc6.write_prg(0x6000, 0x42);
+        assert_eq!(vrc6.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn chr_banking_modes() {
+        // R1 is at $D001 on VRC6a and $D002 on VRC6b
+        for (wiring, r1) in [(Wiring::Vrc6A, 0xD001), (Wiring::Vrc6B, 0xD002)] {
+            let mut vrc6 = new_vrc6(wiring);
+            vrc6.write_prg(0xD000, 0x10);
+            vrc6.write_prg(r1, 0x21);
+            let banks = (0..2).map(|i| vrc6.read_chr(i * 0x400)).collect::<Vec<_>>();
+            assert_eq!(banks, [0x10, 0x21]);
+        }
+
+        let mut vrc6 = new_vrc6(Wiring::Vrc6A);
+        for (register, bank) in [0xD000, 0xD001, 0xD002, 0xD003, 0xE000, 0xE001]
+            .into_iter()
+            .zip([0x10, 0x21, 0x30, 0x40, 0x51, 0x60])
+        {
+            vrc6.write_prg(register, bank);
+        }
+        // mode 1, A10 from the PPU
+        vrc6.write_prg(0xB003, 0x21);
+        let banks = (0..8).map(|i| vrc6.read_chr(i * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks, [0x10, 0x11, 0x20, 0x21, 0x30, 0x31, 0x40, 0x41]);
+        // mode 2, A10 from the bank
+        vrc6.write_prg(0xB003, 0x02);
+        let banks = (0..8).map(|i| vrc6.read_chr(i * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks, [0x10, 0x21, 0x30, 0x40, 0x51, 0x51, 0x60, 0x60]);
+        assert_eq!(vrc6.chr_rom_offset(0x1810), Some(0x60 * 0x400 + 0x10));
+    }
+
+    #[test]
+    fn mirroring_and_irq() {
+        let mut vrc6 = new_vrc6(Wiring::Vrc6B);
+        vrc6.write_prg(0xB003, 0x04);
+        assert_eq!(vrc6.mirroring(), NametableMirroring::Horizontal);
+        vrc6.write_prg(0xB003, 0x0C);
+        assert_eq!(vrc6.mirroring(), NametableMirroring::SingleScreenUpper);
+
+        // the control is at $F002 and the acknowledgement at $F001 through the VRC6b lines
+        vrc6.write_prg(0xF000, 0xF0);
+        vrc6.write_prg(0xF002, 0x06);
+        for _ in 0..15 {
+            vrc6.tick();
+        }
+        assert!(!vrc6.irq());
+        vrc6.tick();
+        assert!(vrc6.irq());
+        vrc6.write_prg(0xF001, 0);
+        assert!(!vrc6.irq());
+    }
+}
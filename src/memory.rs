@@ -3,6 +3,17 @@ pub trait Memory {
     fn write(&mut self, addr: u16, data: u8);
 }
 
+/// Contents of RAM after power-on, which are undefined on real hardware
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RamFill {
+    #[default]
+    Zeros,
+    /// Every byte is $FF
+    Ones,
+    /// Pseudo-random bytes, the same seed always produces the same contents
+    Random(u64),
+}
+
 pub struct Ram(Vec<u8>);
 
 impl Ram {
@@ -10,6 +21,27 @@ impl Ram {
         Self(vec![0; size])
     }
 
+    pub fn with_fill(size: usize, fill: RamFill) -> Self {
+        match fill {
+            RamFill::Zeros => Self::new(size),
+            RamFill::Ones => Self(vec![0xFF; size]),
+            RamFill::Random(seed) => {
+                // splitmix64
+                let mut state = seed;
+                let bytes = (0..size)
+                    .map(|_| {
+                        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                        let mut z = state;
+                        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                        (z ^ (z >> 31)) as u8
+                    })
+                    .collect();
+                Self(bytes)
+            }
+        }
+    }
+
     pub fn size(&self) -> usize {
         self.0.len()
     }
@@ -82,4 +114,19 @@ pub mod test {
             self.as_ref().borrow_mut().write(addr, data);
         }
     }
+
+    #[test]
+    fn ram_fill() {
+        use crate::memory::{Ram, RamFill};
+
+        let ram = Ram::with_fill(0x800, RamFill::Ones);
+        assert!((0..0x800).all(|a| ram.read(a) == 0xFF));
+
+        let ram = Ram::with_fill(0x800, RamFill::Random(42));
+        let same_seed = Ram::with_fill(0x800, RamFill::Random(42));
+        let other_seed = Ram::with_fill(0x800, RamFill::Random(43));
+        assert!((0..0x800).all(|a| ram.read(a) == same_seed.read(a)));
+        assert!((0..0x800).any(|a| ram.read(a) != other_seed.read(a)));
+        assert!((0..0x800).any(|a| ram.read(a) != ram.read(0)));
+    }
 }
@@ -2,10 +2,14 @@ use std::fs::File;
 use std::io::Read;
 use std::fmt;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum NametableMirroring {
     Vertical,
     Horizontal,
+    /// All nametables show the first 1 KiB of VRAM, selected by the mapper
+    SingleScreenLower,
+    /// All nametables show the second 1 KiB of VRAM, selected by the mapper
+    SingleScreenUpper,
 }
 
 impl NametableMirroring {
@@ -49,7 +53,7 @@ pub struct NesRom {
     pub nametable_mirroring: NametableMirroring,
     battery_backed_prg_ram: bool,
     prg_ram_size: u8,
-    tv_system: TvSystem,
+    pub tv_system: TvSystem,
 }
 
 impl fmt::Debug for NesRom {
@@ -146,4 +150,8 @@ impl NesRom {
         })
     }
 
+    /// The iNES mapper number, which identifies the cartridge board
+    pub fn mapper(&self) -> u8 {
+        self.mapper
+    }
 }
@@ -0,0 +1,120 @@
+use crate::nes_rom::TvSystem;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+pub const PAL_FRAME_RATE: f64 = 50.0070;
+
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 8.0;
+
+// when we fall further behind than this we stop trying to catch up
+const MAX_FRAME_LAG: u32 = 4;
+
+/// Synchronizes the emulation to wall-clock time once per frame.
+///
+/// The emulator runs at full speed for one frame's worth of cycles and then sleeps until the
+/// frame would have ended on real hardware.
+pub struct Pacer {
+    frame_rate: f64,
+    speed: f64,
+    throttled: bool,
+    deadline: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(tv_system: &TvSystem) -> Self {
+        let frame_rate = match tv_system {
+            TvSystem::Ntsc => NTSC_FRAME_RATE,
+            TvSystem::Pal => PAL_FRAME_RATE,
+        };
+        Self {
+            frame_rate,
+            speed: 1.0,
+            throttled: true,
+            deadline: None,
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.deadline = None;
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    pub fn set_throttled(&mut self, throttled: bool) {
+        self.throttled = throttled;
+        self.deadline = None;
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / (self.frame_rate * self.speed))
+    }
+
+    /// Called once a frame has been emulated, blocks until it is due
+    pub fn end_frame(&mut self) {
+        if !self.throttled {
+            return;
+        }
+
+        let now = Instant::now();
+        let frame_duration = self.frame_duration();
+        let deadline = match self.deadline {
+            Some(deadline) if now <= deadline + frame_duration * MAX_FRAME_LAG => {
+                deadline + frame_duration
+            }
+            _ => now + frame_duration,
+        };
+
+        if deadline > now {
+            sleep(deadline - now);
+        }
+        self.deadline = Some(deadline);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::nes_rom::TvSystem;
+    use crate::pacing::{Pacer, MAX_SPEED};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn frame_duration() {
+        let mut pacer = Pacer::new(&TvSystem::Ntsc);
+        assert_eq!(pacer.frame_duration().as_micros(), 16639);
+
+        pacer.set_speed(2.0);
+        assert_eq!(pacer.frame_duration().as_micros(), 8319);
+
+        pacer.set_speed(100.0);
+        assert_eq!(pacer.speed(), MAX_SPEED);
+
+        let pacer = Pacer::new(&TvSystem::Pal);
+        assert_eq!(pacer.frame_duration().as_micros(), 19997);
+    }
+
+    #[test]
+    fn end_frame() {
+        let mut pacer = Pacer::new(&TvSystem::Ntsc);
+        let start = Instant::now();
+        for _ in 0..4 {
+            pacer.end_frame();
+        }
+        assert!(start.elapsed() >= pacer.frame_duration() * 4);
+
+        pacer.set_throttled(false);
+        let start = Instant::now();
+        for _ in 0..100 {
+            pacer.end_frame();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -1,9 +1,19 @@
+use crate::mapper::Mapper;
 use crate::memory::Memory;
-use crate::nes_rom::NametableMirroring;
 use crate::ppu::ppu_memory::PpuMemory;
 
 pub mod ppu_memory;
 
+/// The memory the PPU is connected to, which lets the cartridge watch the address bus
+pub trait PpuBus: Memory {
+    /// The PPU put `addr` on the address bus, either to fetch from the pattern tables while
+    /// rendering or for an access through PPUADDR and PPUDATA
+    ///
+    /// Each bit plane of a tile is reported with its own address, so the cartridge sees both the
+    /// tile and the row that is fetched.
+    fn set_address(&mut self, _addr: u16) {}
+}
+
 struct PpuAddr {
     hi: u8,
     lo: u8,
@@ -108,11 +118,15 @@ const PPU_MASK_BLUE_BIT: u8 = 7;
 
 const SCANLINES: u32 = 262;
 const VISIBLE_SCANLIENS: u32 = 240;
+const PRE_RENDER_SCANLINE: u32 = SCANLINES - 1;
 const SCANLINE_CYCLES: u32 = 341;
+/// The dot of the first pattern fetch for the sprites of the next line
+const SPRITE_FETCH_CYCLE: u32 = 261;
+const SPRITES_PER_LINE: usize = 8;
 
 pub const OAM_SIZE: usize = 0x100;
 
-pub struct Ppu<M: Memory> {
+pub struct Ppu<M: PpuBus> {
     ctrl: u8,
     mask: u8,
     status: u8,
@@ -121,17 +135,22 @@ pub struct Ppu<M: Memory> {
     addr: PpuAddr,
     data_buffer: u8,
 
-    scanline: u32,
+    pub scanline: u32,
     pub cycle: u32,
     nmi: bool,
     new_frame: bool,
+    /// Writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are ignored until the first frame after
+    /// power-on or reset has been drawn
+    warming_up: bool,
 
     pub memory: M,
     pub oam: [u8; OAM_SIZE],
+    /// The pattern addresses of the sprites on the next line, found by sprite evaluation
+    sprite_patterns: [u16; SPRITES_PER_LINE],
 }
 
 impl Ppu<PpuMemory> {
-    pub fn new(chr_rom: Vec<u8>, mirroring: NametableMirroring) -> Self {
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
         Self {
             ctrl: 0,
             mask: 0,
@@ -140,23 +159,50 @@ impl Ppu<PpuMemory> {
             scroll: PpuScroll::new(),
             addr: PpuAddr::new(),
             data_buffer: 0,
-            scanline: 1,
+            scanline: 0,
             cycle: 0,
             nmi: false,
             new_frame: false,
-            memory: PpuMemory::new(chr_rom, mirroring),
+            warming_up: true,
+            memory: PpuMemory::new(mapper),
             oam: [0; OAM_SIZE],
+            sprite_patterns: [0; SPRITES_PER_LINE],
         }
     }
 }
 
-impl<M: Memory> Ppu<M> {
+impl<M: PpuBus> Ppu<M> {
+    /// The reset line clears the registers and restarts the frame, VRAM, OAM and PPUADDR are kept
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.scroll = PpuScroll::new();
+        self.addr.reset_latch();
+        self.data_buffer = 0;
+        self.scanline = 0;
+        self.cycle = 0;
+        self.nmi = false;
+        self.warming_up = true;
+    }
+
     pub fn tick(&mut self, delta: u32) {
-        self.cycle += delta;
-        while self.cycle > SCANLINE_CYCLES {
-            self.cycle -= SCANLINE_CYCLES;
-            self.scanline += 1;
+        for _ in 0..delta {
+            self.cycle += 1;
+            if self.cycle == SCANLINE_CYCLES {
+                self.cycle = 0;
+                self.scanline = (self.scanline + 1) % SCANLINES;
+            }
+
+            // pattern fetches happen on the fifth and seventh dot of every 8 dot fetch cycle
+            if self.cycle & 0b101 == 0b101 && self.rendering_enabled() {
+                self.fetch_patterns();
+            }
 
+            if self.cycle != 1 {
+                continue;
+            }
+
+            // the status flags change on the second dot of the first vblank and the pre-render line
             if self.scanline == VISIBLE_SCANLIENS + 1 {
                 self.set_status_bit(PPU_STATUS_VBLANK_BIT, true);
                 if self.get_ctrl_bit(PPU_CTRL_VBLANK_NMI_BIT) {
@@ -164,8 +210,8 @@ impl<M: Memory> Ppu<M> {
                 }
             }
 
-            if self.scanline > SCANLINES {
-                self.scanline = 0;
+            if self.scanline == SCANLINES - 1 {
+                self.warming_up = false;
                 self.nmi = false;
                 self.set_status_bit(PPU_STATUS_SPRITE_HIT_BIT, false);
                 self.set_status_bit(PPU_STATUS_VBLANK_BIT, false);
@@ -174,12 +220,101 @@ impl<M: Memory> Ppu<M> {
         }
     }
 
+    fn rendering_enabled(&self) -> bool {
+        self.get_mask_bit(PPU_MASK_BACKGROUND_RENDERING_BIT)
+            || self.get_mask_bit(PPU_MASK_SPRITE_RENDERING_BIT)
+    }
+
+    /// Shows the pattern fetches of the current dot on the address bus
+    ///
+    /// Frames are drawn at once by the renderer, only the addresses are reproduced for mappers
+    /// that watch them. Scrolling is ignored like in the renderer.
+    fn fetch_patterns(&mut self) {
+        if self.scanline >= VISIBLE_SCANLIENS && self.scanline != PRE_RENDER_SCANLINE {
+            return;
+        }
+        // the high bit plane is fetched two dots after the low one
+        let plane = if self.cycle & 0b10 != 0 { 8 } else { 0 };
+        let next_line = if self.scanline == PRE_RENDER_SCANLINE {
+            0
+        } else {
+            self.scanline + 1
+        };
+        // the pre-render line fetches tiles that are never shown, those of the first line are
+        // used in their place
+        let line = if self.scanline == PRE_RENDER_SCANLINE {
+            0
+        } else {
+            self.scanline
+        };
+        let addr = match self.cycle {
+            // tiles 0 and 1 of a line are fetched at the end of the previous one
+            1..=256 => self.background_pattern(self.cycle / 8 + 2, line),
+            SPRITE_FETCH_CYCLE..=320 => {
+                if self.cycle == SPRITE_FETCH_CYCLE {
+                    self.evaluate_sprites();
+                }
+                self.sprite_patterns[((self.cycle - SPRITE_FETCH_CYCLE) / 8) as usize]
+            }
+            321..=336 => self.background_pattern((self.cycle - 321) / 8, next_line),
+            _ => return,
+        };
+        self.memory.set_address(addr + plane);
+    }
+
+    fn background_pattern(&self, column: u32, line: u32) -> u16 {
+        let nametable_addr = 0x2000 + self.base_nametable_index() as u16 * 0x400;
+        // 30 rows of tiles, the attribute table follows
+        let tile_index = (line / 8 % 30 * 32 + column % 32) as u16;
+        let tile = self.memory.read(nametable_addr + tile_index) as u16;
+        self.background_pattern_addr() + tile * 16 + (line % 8) as u16
+    }
+
+    /// Finds the first 8 sprites on the next line, unused slots fetch tile $FF
+    fn evaluate_sprites(&mut self) {
+        let height = if self.tall_sprites() { 16 } else { 8 };
+        let sprites = self
+            .oam
+            .chunks_exact(4)
+            .filter(|sprite| {
+                // nothing is evaluated on the pre-render line
+                self.scanline != PRE_RENDER_SCANLINE
+                    && self.scanline.wrapping_sub(sprite[0] as u32) < height
+            })
+            .map(|sprite| {
+                let (tile, flip_vertically) = (sprite[1] as u16, sprite[2] & 0x80 != 0);
+                let mut row = (self.scanline - sprite[0] as u32) as u16;
+                if flip_vertically {
+                    row = height as u16 - 1 - row;
+                }
+                if self.tall_sprites() {
+                    (tile & 1) * 0x1000 + (tile & 0xFE) * 16 + (row & 8) * 2 + (row & 7)
+                } else {
+                    self.sprite_pattern_addr() + tile * 16 + row
+                }
+            });
+        let unused = if self.tall_sprites() {
+            0x1000 + 0xFE * 16
+        } else {
+            self.sprite_pattern_addr() + 0xFF * 16
+        };
+        let mut patterns = [unused; SPRITES_PER_LINE];
+        for (pattern, sprite) in patterns.iter_mut().zip(sprites) {
+            *pattern = sprite;
+        }
+        self.sprite_patterns = patterns;
+    }
+
     pub fn poll_nmi(&mut self) -> bool {
         let value = self.nmi;
         self.nmi = false;
         value
     }
 
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi
+    }
+
     pub fn poll_new_frame(&mut self) -> bool {
         let value = self.new_frame;
         self.new_frame = false;
@@ -191,6 +326,9 @@ impl<M: Memory> Ppu<M> {
     }
 
     pub fn write_ppu_ctrl(&mut self, value: u8) {
+        if self.warming_up {
+            return;
+        }
         let old_nmi = self.get_ctrl_bit(PPU_CTRL_VBLANK_NMI_BIT);
         self.ctrl = value;
         if !old_nmi && self.get_ctrl_bit(PPU_CTRL_VBLANK_NMI_BIT) && self.is_vblank() {
@@ -203,7 +341,9 @@ impl<M: Memory> Ppu<M> {
     }
 
     pub fn write_ppu_mask(&mut self, value: u8) {
-        self.mask = value;
+        if !self.warming_up {
+            self.mask = value;
+        }
     }
 
     pub fn get_mask_bit(&self, bit: u8) -> bool {
@@ -252,15 +392,29 @@ impl<M: Memory> Ppu<M> {
     }
 
     pub fn write_ppu_scroll(&mut self, value: u8) {
-        self.scroll.set(value);
+        if !self.warming_up {
+            self.scroll.set(value);
+        }
     }
 
     pub fn write_ppu_addr(&mut self, value: u8) {
-        self.addr.set(value)
+        if !self.warming_up {
+            self.addr.set(value);
+            // the address is on the bus once both bytes are written
+            if self.addr.is_hi {
+                self.memory.set_address(self.addr.get_addr());
+            }
+        }
+    }
+
+    /// The address the next PPUDATA access goes to
+    pub fn vram_addr(&self) -> u16 {
+        self.addr.get_addr()
     }
 
     pub fn read_ppu_data(&mut self) -> u8 {
         let addr = self.addr.get_addr();
+        self.memory.set_address(addr);
         let result = if (0x3F00..=0x3FFF).contains(&addr) {
             self.memory.read(addr)
         } else {
@@ -274,6 +428,7 @@ impl<M: Memory> Ppu<M> {
     }
 
     pub fn write_ppu_data(&mut self, value: u8) {
+        self.memory.set_address(self.addr.get_addr());
         self.memory.write(self.addr.get_addr(), value);
 
         self.addr.increment_addr(self.addr_increment_amount());
@@ -314,13 +469,21 @@ impl<M: Memory> Ppu<M> {
 
 #[cfg(test)]
 mod test {
+    use crate::mapper::nrom::Nrom;
     use crate::memory::test::DummyMemory;
-    use crate::memory::Memory;
-    use crate::ppu::{Ppu, PpuAddr, PpuScroll, OAM_SIZE, PPU_CTRL_VRAM_ADD_INCREMENT_BIT};
+    use crate::memory::RamFill;
+    use crate::nes_rom::NametableMirroring;
+    use crate::ppu::{
+        Ppu, PpuAddr, PpuBus, PpuScroll, OAM_SIZE, PPU_CTRL_VRAM_ADD_INCREMENT_BIT, SCANLINES,
+        SCANLINE_CYCLES, SPRITES_PER_LINE,
+    };
     use std::cell::RefCell;
     use std::rc::Rc;
 
-    impl<M: Memory> Ppu<M> {
+    impl PpuBus for DummyMemory {}
+    impl PpuBus for Rc<RefCell<DummyMemory>> {}
+
+    impl<M: PpuBus> Ppu<M> {
         fn new_with_memory(memory: M) -> Self {
             Self {
                 ctrl: 0,
@@ -330,12 +493,14 @@ mod test {
                 scroll: PpuScroll::new(),
                 addr: PpuAddr::new(),
                 data_buffer: 0,
-                scanline: 1,
+                scanline: 0,
                 cycle: 0,
                 nmi: false,
                 new_frame: false,
+                warming_up: false,
                 memory,
                 oam: [0; OAM_SIZE],
+                sprite_patterns: [0; SPRITES_PER_LINE],
             }
         }
 
@@ -389,4 +554,45 @@ mod test {
         ppu.read_ppu_data();
         assert_eq!(ppu.addr.get_addr(), 0x0000); // wraparound after 0x3fff
     }
+
+    #[test]
+    pub fn test_ignores_writes_after_reset() {
+        let mut ppu = Ppu::new_with_memory(DummyMemory::new());
+        ppu.write_ppu_ctrl(0x80);
+        ppu.write_ppu_addr(0x21);
+        ppu.reset();
+        assert_eq!(ppu.ctrl, 0);
+
+        ppu.write_ppu_ctrl(0x80);
+        ppu.write_ppu_mask(0x1E);
+        ppu.write_ppu_scroll(0x10);
+        ppu.write_ppu_addr(0x22);
+        assert_eq!((ppu.ctrl, ppu.mask, ppu.scroll.x), (0, 0, 0));
+        // PPUADDR keeps its value from before the reset
+        assert_eq!(ppu.addr.get_addr(), 0x2100);
+
+        // the registers are writable again at the pre-render line
+        ppu.tick((SCANLINES - 1) * SCANLINE_CYCLES + 1);
+        ppu.write_ppu_ctrl(0x80);
+        assert_eq!(ppu.ctrl, 0x80);
+    }
+
+    #[test]
+    pub fn test_renders_every_nametable() {
+        for nametable in 0..4 {
+            let prg_rom = vec![0; 0x4000];
+            let mapper = Nrom::new(
+                prg_rom,
+                vec![],
+                NametableMirroring::Vertical,
+                RamFill::Zeros,
+            );
+            let mut ppu = Ppu::new(Box::new(mapper));
+            ppu.tick(SCANLINES * SCANLINE_CYCLES);
+            ppu.write_ppu_ctrl(nametable);
+            ppu.write_ppu_mask(0x18);
+            // the fetches stay inside the selected nametable on every line
+            ppu.tick(SCANLINES * SCANLINE_CYCLES);
+        }
+    }
 }
@@ -1,47 +1,40 @@
+use crate::mapper::Mapper;
 use crate::memory::{Memory, Ram};
 use crate::nes_rom::NametableMirroring;
+use crate::ppu::PpuBus;
 
 pub struct PpuMemory {
-    pub chr_rom: Vec<u8>,
+    /// The cartridge, its PRG side is reached by the CPU through the bus
+    pub mapper: Box<dyn Mapper>,
     pub vram: Ram,
-    mirroring: NametableMirroring,
     pub palette_table: Ram,
 }
 
 impl PpuMemory {
-    pub fn new(chr_rom: Vec<u8>, mirroring: NametableMirroring) -> Self {
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
         Self {
-            chr_rom,
+            mapper,
             vram: Ram::new(0x800),
-            mirroring,
             palette_table: Ram::new(0x20),
         }
     }
 
     fn mirror_vram_addr(&self, vram_addr: u16) -> u16 {
-        match self.mirroring {
-            NametableMirroring::Vertical => match vram_addr {
-                0x000..0x400 => vram_addr,
-                0x400..0x800 => vram_addr,
-                0x800..0xC00 => vram_addr - 0x800,
-                0xC00..0x1000 => vram_addr - 0x800,
-                _ => unreachable!(),
-            },
-            NametableMirroring::Horizontal => match vram_addr {
-                0x000..0x400 => vram_addr,
-                0x400..0x800 => vram_addr - 0x400,
-                0x800..0xC00 => vram_addr - 0x400,
-                0xC00..0x1000 => vram_addr - 0x800,
-                _ => unreachable!(),
-            },
-        }
+        // the 1 KiB of VRAM shown by each of the four nametables
+        let pages = match self.mapper.mirroring() {
+            NametableMirroring::Vertical => [0, 1, 0, 1],
+            NametableMirroring::Horizontal => [0, 0, 1, 1],
+            NametableMirroring::SingleScreenLower => [0; 4],
+            NametableMirroring::SingleScreenUpper => [1; 4],
+        };
+        pages[(vram_addr / 0x400) as usize] * 0x400 + vram_addr % 0x400
     }
 }
 
 impl Memory for PpuMemory {
     fn read(&self, addr: u16) -> u8 {
         if addr < 0x2000 {
-            self.chr_rom[addr as usize]
+            self.mapper.read_chr(addr)
         } else if (0x2000..0x3000).contains(&addr) {
             let vram_addr = addr - 0x2000;
             self.vram.read(self.mirror_vram_addr(vram_addr))
@@ -57,7 +50,7 @@ impl Memory for PpuMemory {
 
     fn write(&mut self, addr: u16, data: u8) {
         if addr < 0x2000 {
-            println!("Attempted write to CHR ROM at {:#X}", addr);
+            self.mapper.write_chr(addr, data);
         } else if (0x2000..0x3000).contains(&addr) {
             let vram_addr = addr - 0x2000;
             self.vram.write(self.mirror_vram_addr(vram_addr), data);
@@ -71,3 +64,9 @@ impl Memory for PpuMemory {
         }
     }
 }
+
+impl PpuBus for PpuMemory {
+    fn set_address(&mut self, addr: u16) {
+        self.mapper.ppu_address(addr);
+    }
+}
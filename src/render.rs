@@ -1,8 +1,9 @@
 mod sprite;
 
+use crate::cpu::bus::Bus;
+use crate::cpu::cdl::{CodeDataLogger, CDL_CHR_RENDERED};
 use crate::cpu::Cpu;
 use crate::memory::Memory;
-use crate::nes_rom::NesRom;
 use crate::ppu::ppu_memory::PpuMemory;
 use crate::ppu::{Ppu, OAM_SIZE};
 use crate::render::sprite::Sprite;
@@ -43,21 +44,22 @@ pub fn get_sprite_palette(ppu: &Ppu<PpuMemory>, palette_idx: u16) -> [Color; 4]
     get_bg_palette(ppu, palette_idx + 4)
 }
 
-pub async fn render_frame(cpu: &mut Cpu) {
+pub async fn render_frame(cpu: &mut Cpu<Bus>) {
     let ppu = &mut cpu.bus.ppu;
+    let mut cdl = cpu.bus.cdl.as_mut();
 
     request_new_screen_size(
         RENDER_SCALE * (8 * 32) as f32,
         RENDER_SCALE * (8 * 30) as f32,
     );
 
-    render_background(ppu).await;
-    render_sprites(ppu).await;
+    render_background(ppu, cdl.as_deref_mut()).await;
+    render_sprites(ppu, cdl).await;
 
     next_frame().await
 }
 
-async fn render_background(ppu: &mut Ppu<PpuMemory>) {
+async fn render_background(ppu: &mut Ppu<PpuMemory>, mut cdl: Option<&mut CodeDataLogger>) {
     let bank = ppu.background_pattern_addr();
 
     fn calc_screen_pos(tile_index: usize, pixel_index: usize) -> (f32, f32) {
@@ -76,9 +78,7 @@ async fn render_background(ppu: &mut Ppu<PpuMemory>) {
         let nametable_addr = 0x2000 + (ppu.base_nametable_index() as u16 * 0x400);
         // which tile are we rendering?
         let tile = ppu.memory.read(nametable_addr + tile_index) as u16;
-        let chr_data = ppu.memory.chr_rom
-            [(bank + tile * 16) as usize..(bank + tile * 16 + 16) as usize]
-            .to_vec();
+        let chr_data = read_tile(&ppu.memory, bank + tile * 16, cdl.as_deref_mut());
         let pixels = chr_data_to_pixels(chr_data);
 
         // which palette should be used?
@@ -108,7 +108,7 @@ async fn render_background(ppu: &mut Ppu<PpuMemory>) {
     }
 }
 
-async fn render_sprites(ppu: &Ppu<PpuMemory>) {
+async fn render_sprites(ppu: &Ppu<PpuMemory>, mut cdl: Option<&mut CodeDataLogger>) {
     for oam_idx in (0..OAM_SIZE).step_by(4).rev() {
         let sprite = Sprite::from_data(&ppu.oam[oam_idx..oam_idx + 4], ppu.tall_sprites());
         if !sprite.visible {
@@ -121,12 +121,12 @@ async fn render_sprites(ppu: &Ppu<PpuMemory>) {
             if sprite.flip_vertically {
                 (top_tile, bottom_tile) = (bottom_tile, top_tile);
             }
-            render_sprite_tile(&sprite, ppu, bank, top_tile, 0).await;
-            render_sprite_tile(&sprite, ppu, bank, bottom_tile, 8).await;
+            render_sprite_tile(&sprite, ppu, cdl.as_deref_mut(), bank, top_tile, 0).await;
+            render_sprite_tile(&sprite, ppu, cdl.as_deref_mut(), bank, bottom_tile, 8).await;
         } else {
             let bank = ppu.sprite_pattern_addr();
             let tile = sprite.tile_index;
-            render_sprite_tile(&sprite, ppu, bank, tile, 0).await;
+            render_sprite_tile(&sprite, ppu, cdl.as_deref_mut(), bank, tile, 0).await;
         }
     }
 }
@@ -134,12 +134,12 @@ async fn render_sprites(ppu: &Ppu<PpuMemory>) {
 async fn render_sprite_tile(
     sprite: &Sprite,
     ppu: &Ppu<PpuMemory>,
+    cdl: Option<&mut CodeDataLogger>,
     bank: u16,
     tile: u16,
     y_offset: u16,
 ) {
-    let chr_data =
-        ppu.memory.chr_rom[(bank + tile * 16) as usize..(bank + tile * 16 + 16) as usize].to_vec();
+    let chr_data = read_tile(&ppu.memory, bank + tile * 16, cdl);
     let pixels = chr_data_to_pixels(chr_data);
 
     let colors = get_sprite_palette(ppu, sprite.palette as u16);
@@ -170,7 +170,17 @@ async fn render_sprite_tile(
     }
 }
 
-pub async fn debug_chr_rom(rom: &NesRom) {
+/// Reads the 16 bytes of a tile from the pattern tables through the mapper
+fn read_tile(memory: &PpuMemory, addr: u16, cdl: Option<&mut CodeDataLogger>) -> Vec<u8> {
+    if let (Some(cdl), Some(offset)) = (cdl, memory.mapper.chr_rom_offset(addr)) {
+        // tiles never cross the 1 KiB granularity of CHR banks
+        cdl.log_chr(offset..offset + 16, CDL_CHR_RENDERED);
+    }
+    (addr..addr + 16).map(|addr| memory.read(addr)).collect()
+}
+
+/// Shows both pattern tables as they are currently mapped
+pub async fn debug_chr_rom(memory: &PpuMemory) {
     request_new_screen_size(
         RENDER_SCALE * (2 * 8 * 16) as f32,
         RENDER_SCALE * (8 * 16) as f32,
@@ -190,9 +200,8 @@ pub async fn debug_chr_rom(rom: &NesRom) {
 
     const COLORS: [Color; 4] = [BLACK, RED, BLUE, WHITE];
 
-    let chr_rom = &rom.chr_rom;
     for tile in 0..256 {
-        let chr_data = chr_rom[tile * 16..(tile + 1) * 16].to_vec();
+        let chr_data = read_tile(memory, tile as u16 * 16, None);
         let pixels = chr_data_to_pixels(chr_data);
         for i in 0..pixels.len() {
             let (screen_x, screen_y) = calc_screen_pos(tile, i);
@@ -206,7 +215,7 @@ pub async fn debug_chr_rom(rom: &NesRom) {
         }
     }
     for tile in 256..512 {
-        let chr_data = chr_rom[tile * 16..(tile + 1) * 16].to_vec();
+        let chr_data = read_tile(memory, tile as u16 * 16, None);
         let pixels = chr_data_to_pixels(chr_data);
         for i in 0..pixels.len() {
             let (mut screen_x, mut screen_y) = calc_screen_pos(tile, i);